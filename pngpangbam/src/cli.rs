@@ -1,3 +1,5 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 
 use pngpanglib::png_command::{PngCommand, PngCmdSuccess};
@@ -19,7 +21,7 @@ impl Cli {
         PngCommand::exec(&self.command)
     }
 
-    pub fn exec_and_display(&self) {
+    pub fn exec_and_display(&self) -> ExitCode {
         match self.exec() {
             Ok(success) => {
                 match success {
@@ -32,39 +34,53 @@ impl Cli {
                     PngCmdSuccess::SuccessBytes(b) => {
                         println!("Bytes: {b:02X?}");
                     }
+                    #[allow(deprecated)]
                     PngCmdSuccess::SuccessHashMap(h) => {
                         println!("Chunks: {h:?}");
                     }
                 }
+                ExitCode::SUCCESS
             }
             Err(failure) => {
                 match &failure {
                     PngError::BadHeader => {
-                        println!("Bad header.");
-                    }
-                    PngError::MissingRequiredChunks => {
-                        println!("Malformed PNG: missing required chunks.");
+                        eprintln!("Bad header.");
                     }
-                    PngError::ChunkNotFound => {
-                        println!("Chunk not found.");
+                    PngError::MissingRequiredChunks(_) => {
+                        eprintln!("Malformed PNG: {failure}");
                     }
-                    PngError::GenericError => {
-                        println!("Unspecified error.");
+                    PngError::ChunkNotFound(_) | PngError::ChunkTypeError(_) => {
+                        eprintln!("{failure}");
                     }
-                    PngError::IO(_) => {
-                        println!("I/O error reading file.");
+                    PngError::IO(e) => {
+                        eprintln!("I/O error: {e}");
                     }
                     PngError::ChunkError(chunk) => {
-                        println!("Chunk error: {chunk}");
+                        eprintln!("Chunk error: {chunk}");
+                    }
+                    PngError::BadChunk { .. } => {
+                        eprintln!("Malformed PNG: {failure}");
+                    }
+                    PngError::MalformedChunk(_) | PngError::BadAnimation(_) | PngError::BadSequence { .. } => {
+                        eprintln!("Malformed PNG: {failure}");
+                    }
+                    PngError::BadImageData(_) => {
+                        eprintln!("Malformed PNG: {failure}");
+                    }
+                    PngError::NotAnimated | PngError::FrameNotFound(_) | PngError::Unsupported(_) |
+                    PngError::CapacityExceeded { .. } | PngError::NoPayload | PngError::BadPayload(_) |
+                    PngError::BadSignature(_) | PngError::InvalidEdit(_) => {
+                        eprintln!("{failure}");
                     }
                 }
+                ExitCode::FAILURE
             }
         }
     }
 
-    pub fn run() {
+    pub fn run() -> ExitCode {
         let cmd = Cli::init();
 
-        cmd.exec_and_display();
+        cmd.exec_and_display()
     }
 }
\ No newline at end of file
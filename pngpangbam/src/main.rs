@@ -1,7 +1,9 @@
 mod cli;
 
+use std::process::ExitCode;
+
 use crate::cli::Cli;
 
-fn main() {
-    Cli::run();
+fn main() -> ExitCode {
+    Cli::run()
 }
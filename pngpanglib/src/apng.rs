@@ -0,0 +1,641 @@
+//! Support for animated PNG (APNG) files.
+//!
+//! APNG adds three chunk types to a standard PNG: acTL (animation control), fcTL (frame control)
+//! and fdAT (frame data). The fcTL and fdAT chunks share a single sequence number counter
+//! starting at zero. See <https://wiki.mozilla.org/APNG_Specification> for details.
+
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::png::{Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// The chunk type of the animation control chunk.
+pub const ACTL: &str = "acTL";
+/// The chunk type of the frame control chunk.
+pub const FCTL: &str = "fcTL";
+/// The chunk type of the frame data chunk.
+pub const FDAT: &str = "fdAT";
+
+/// Test if a chunk type is one of the APNG chunk types.
+pub fn is_apng_type(chunk_type: &ChunkType) -> bool {
+    chunk_type == ACTL || chunk_type == FCTL || chunk_type == FDAT
+}
+
+/// Contents of the acTL chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationControl {
+    /// Number of frames in the animation.
+    pub num_frames: u32,
+    /// Number of times to loop the animation; 0 loops forever.
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    /// Encode as an acTL chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.num_frames.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.num_plays.to_be_bytes());
+        Chunk::new(&ChunkType::from_str(ACTL).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        if chunk.chunk_type() != ACTL || data.len() != 8 {
+            return Err(PngError::MalformedChunk(chunk.chunk_type().to_string()));
+        }
+
+        Ok(AnimationControl {
+            num_frames: be_u32(&data[0..4]),
+            num_plays: be_u32(&data[4..8]),
+        })
+    }
+}
+
+/// How the frame area is treated before rendering the next frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    /// Leave the frame area as it is.
+    None,
+    /// Clear the frame area to fully transparent black.
+    Background,
+    /// Revert the frame area to its contents before this frame was rendered.
+    Previous,
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(DisposeOp::None),
+            1 => Ok(DisposeOp::Background),
+            2 => Ok(DisposeOp::Previous),
+            _ => Err(PngError::MalformedChunk(FCTL.to_string())),
+        }
+    }
+}
+
+impl FromStr for DisposeOp {
+    type Err = PngError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(DisposeOp::None),
+            "background" => Ok(DisposeOp::Background),
+            "previous" => Ok(DisposeOp::Previous),
+            _ => Err(PngError::BadAnimation(format!("unknown dispose op '{s}'"))),
+        }
+    }
+}
+
+/// How the frame is combined with the output buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    /// Overwrite the frame area, including alpha.
+    Source,
+    /// Alpha-composite the frame over the output buffer.
+    Over,
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(BlendOp::Source),
+            1 => Ok(BlendOp::Over),
+            _ => Err(PngError::MalformedChunk(FCTL.to_string())),
+        }
+    }
+}
+
+impl FromStr for BlendOp {
+    type Err = PngError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "source" => Ok(BlendOp::Source),
+            "over" => Ok(BlendOp::Over),
+            _ => Err(PngError::BadAnimation(format!("unknown blend op '{s}'"))),
+        }
+    }
+}
+
+/// Contents of the fcTL chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameControl {
+    /// Position in the shared fcTL/fdAT sequence.
+    pub sequence_number: u32,
+    /// Width of the frame region.
+    pub width: u32,
+    /// Height of the frame region.
+    pub height: u32,
+    /// Horizontal position of the frame region.
+    pub x_offset: u32,
+    /// Vertical position of the frame region.
+    pub y_offset: u32,
+    /// Frame delay numerator.
+    pub delay_num: u16,
+    /// Frame delay denominator; 0 is treated as 100.
+    pub delay_den: u16,
+    /// Disposal of the frame region after rendering.
+    pub dispose_op: DisposeOp,
+    /// Blending of the frame region into the output buffer.
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    /// Get the frame delay.
+    pub fn delay(&self) -> Duration {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den as u64 };
+        Duration::from_micros(self.delay_num as u64 * 1_000_000 / den)
+    }
+
+    /// Encode as an fcTL chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::<u8>::with_capacity(26);
+        for v in [self.sequence_number, self.width, self.height, self.x_offset, self.y_offset] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.push(self.dispose_op as u8);
+        data.push(self.blend_op as u8);
+        Chunk::new(&ChunkType::from_str(FCTL).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        if chunk.chunk_type() != FCTL || data.len() != 26 {
+            return Err(PngError::MalformedChunk(chunk.chunk_type().to_string()));
+        }
+
+        Ok(FrameControl {
+            sequence_number: be_u32(&data[0..4]),
+            width: be_u32(&data[4..8]),
+            height: be_u32(&data[8..12]),
+            x_offset: be_u32(&data[12..16]),
+            y_offset: be_u32(&data[16..20]),
+            delay_num: u16::from_be_bytes([data[20], data[21]]),
+            delay_den: u16::from_be_bytes([data[22], data[23]]),
+            dispose_op: DisposeOp::try_from(data[24])?,
+            blend_op: BlendOp::try_from(data[25])?,
+        })
+    }
+}
+
+/// Contents of the fdAT chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameData {
+    /// Position in the shared fcTL/fdAT sequence.
+    pub sequence_number: u32,
+    /// Compressed image data, continuing the frame's zlib stream.
+    pub data: Vec<u8>,
+}
+
+impl FrameData {
+    /// Encode as an fdAT chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.sequence_number.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.data);
+        Chunk::new(&ChunkType::from_str(FDAT).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameData {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        if chunk.chunk_type() != FDAT || data.len() < 4 {
+            return Err(PngError::MalformedChunk(chunk.chunk_type().to_string()));
+        }
+
+        Ok(FrameData {
+            sequence_number: be_u32(&data[0..4]),
+            data: data[4..].to_vec(),
+        })
+    }
+}
+
+/// A single animation frame.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The frame's control parameters: region, delay, dispose and blend ops.
+    pub control: FrameControl,
+    /// The frame's compressed image data, concatenated from its IDAT or fdAT chunks.
+    pub data: Vec<u8>,
+    /// True if the frame is the default image, stored in IDAT chunks.
+    pub is_default_image: bool,
+    /// Indices of the chunks from the fcTL to the last data chunk of the frame.
+    pub(crate) chunks: Range<usize>,
+}
+
+/// Iterator over the frames of an animated PNG.
+pub struct Frames<'a> {
+    chunks: &'a [Chunk],
+    pos: usize,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(chunks: &'a [Chunk]) -> Frames<'a> {
+        Frames { chunks, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.pos + self.chunks[self.pos..].iter().position(|c| c.chunk_type() == FCTL)?;
+        let control = match FrameControl::try_from(&self.chunks[start]) {
+            Ok(fc) => fc,
+            Err(e) => {
+                self.pos = self.chunks.len();
+                return Some(Err(e));
+            }
+        };
+
+        let mut data = Vec::<u8>::new();
+        let mut is_default_image = false;
+        let mut end = start + 1;
+        for (i, c) in self.chunks.iter().enumerate().skip(start + 1) {
+            if c.chunk_type() == FCTL || c.chunk_type() == &ChunkType::END_CHUNK {
+                break;
+            }
+            if c.chunk_type() == "IDAT" {
+                is_default_image = true;
+                data.extend_from_slice(c.data());
+                end = i + 1;
+            }
+            else if c.chunk_type() == FDAT && !is_default_image {
+                match FrameData::try_from(c) {
+                    Ok(fd) => data.extend_from_slice(&fd.data),
+                    Err(e) => {
+                        self.pos = self.chunks.len();
+                        return Some(Err(e));
+                    }
+                }
+                end = i + 1;
+            }
+        }
+        self.pos = start + 1;
+
+        Some(Ok(Frame {
+            control,
+            data,
+            is_default_image,
+            chunks: start..end,
+        }))
+    }
+}
+
+impl Png {
+    /// Test if the PNG carries an acTL chunk.
+    pub fn is_animated(&self) -> bool {
+        self.chunks().iter().any(|c| c.chunk_type() == ACTL)
+    }
+
+    /// Get the animation control parameters.
+    pub fn animation_control(&self) -> Result<AnimationControl> {
+        match self.chunks().iter().find(|c| c.chunk_type() == ACTL) {
+            None => Err(PngError::NotAnimated),
+            Some(c) => AnimationControl::try_from(c),
+        }
+    }
+
+    /// Iterate over the animation frames.
+    pub fn frames(&self) -> Frames<'_> {
+        Frames::new(self.chunks())
+    }
+
+    /// Validate the structure of the animation.
+    /// Checks that fcTL and fdAT sequence numbers run from zero without gaps, that acTL precedes
+    /// the image data, that the frame count matches acTL and that each frame fits in the image.
+    pub fn validate_animation(&self) -> Result<()> {
+        let actl = self.animation_control()?;
+        let header = self.image_header()?;
+
+        let actl_pos = self.chunks().iter().position(|c| c.chunk_type() == ACTL);
+        let idat_pos = self.chunks().iter().position(|c| c.chunk_type() == "IDAT");
+        if actl_pos > idat_pos {
+            return Err(PngError::BadAnimation("acTL after image data".to_string()));
+        }
+
+        let mut expected: u32 = 0;
+        for c in self.chunks() {
+            let found = if c.chunk_type() == FCTL {
+                FrameControl::try_from(c)?.sequence_number
+            }
+            else if c.chunk_type() == FDAT {
+                FrameData::try_from(c)?.sequence_number
+            }
+            else {
+                continue;
+            };
+            if found != expected {
+                return Err(PngError::BadSequence { expected, found });
+            }
+            expected += 1;
+        }
+
+        let mut count: u32 = 0;
+        for frame in self.frames() {
+            let fc = frame?.control;
+            if fc.width == 0 || fc.height == 0 ||
+                fc.x_offset as u64 + fc.width as u64 > header.width as u64 ||
+                fc.y_offset as u64 + fc.height as u64 > header.height as u64 {
+                return Err(PngError::BadAnimation(format!("frame {count} outside image bounds")));
+            }
+            count += 1;
+        }
+        if count != actl.num_frames {
+            return Err(PngError::BadAnimation(
+                format!("acTL declares {} frames, found {count}", actl.num_frames)));
+        }
+
+        Ok(())
+    }
+
+    /// Get a single frame by index.
+    pub fn frame(&self, index: usize) -> Result<Frame> {
+        self.frames().nth(index).unwrap_or(Err(PngError::FrameNotFound(index)))
+    }
+
+    /// Extract a frame as a standalone, non-animated PNG.
+    /// The result holds only the frame region; it is not composited over earlier frames.
+    /// Ancillary chunks preceding the image data are copied, except for APNG chunks.
+    pub fn extract_frame(&self, index: usize) -> Result<Png> {
+        if !self.is_animated() {
+            return Err(PngError::NotAnimated);
+        }
+        let frame = self.frame(index)?;
+        let mut header = self.image_header()?;
+        header.width = frame.control.width;
+        header.height = frame.control.height;
+
+        let mut chunks = vec![header.to_chunk()];
+        for c in self.chunks().iter().skip(1) {
+            if c.chunk_type() == "IDAT" || c.chunk_type() == FDAT || c.chunk_type() == &ChunkType::END_CHUNK {
+                break;
+            }
+            if !is_apng_type(c.chunk_type()) {
+                chunks.push(Chunk::new(c.chunk_type(), c.data().to_vec()));
+            }
+        }
+        chunks.push(Chunk::new(&ChunkType::from_str("IDAT").unwrap(), frame.data));
+        chunks.push(Chunk::new(&ChunkType::END_CHUNK, Vec::new()));
+
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// Insert a chunk directly after the data of the given frame.
+    /// Non-APNG chunks carry no sequence number, so the animation numbering is left intact.
+    pub fn insert_after_frame(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        if is_apng_type(chunk.chunk_type()) {
+            return Err(PngError::BadAnimation(format!("cannot insert {} chunk", chunk.chunk_type())));
+        }
+        let frame = self.frame(index)?;
+        self.insert_chunk(frame.chunks.end, chunk);
+        Ok(())
+    }
+}
+
+/// Per-frame settings used when assembling an animation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Frame delay numerator.
+    pub delay_num: u16,
+    /// Frame delay denominator.
+    pub delay_den: u16,
+    /// Disposal of the frame region after rendering.
+    pub dispose_op: DisposeOp,
+    /// Blending of the frame region into the output buffer.
+    pub blend_op: BlendOp,
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        FrameOptions {
+            delay_num: 100,
+            delay_den: 1000,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+}
+
+/// Builds an animated PNG from a sequence of still images of equal dimensions.
+/// The first image becomes the default image; its ancillary chunks are carried over.
+#[derive(Default)]
+pub struct ApngBuilder {
+    frames: Vec<(Png, FrameOptions)>,
+    num_plays: u32,
+}
+
+impl ApngBuilder {
+    /// Create an empty builder that loops forever.
+    pub fn new() -> ApngBuilder {
+        ApngBuilder::default()
+    }
+
+    /// Set the number of times to play the animation; 0 loops forever.
+    pub fn num_plays(mut self, num_plays: u32) -> ApngBuilder {
+        self.num_plays = num_plays;
+        self
+    }
+
+    /// Add a frame.
+    pub fn frame(mut self, png: Png, options: FrameOptions) -> ApngBuilder {
+        self.frames.push((png, options));
+        self
+    }
+
+    /// Assemble the animation.
+    /// All frames must share the header and palette of the first frame.
+    pub fn build(self) -> Result<Png> {
+        let (first, _) = self.frames.first()
+            .ok_or_else(|| PngError::BadAnimation("no frames".to_string()))?;
+        let header = first.image_header()?;
+        let palette = first.chunks().iter().find(|c| c.chunk_type() == "PLTE").map(|c| c.data());
+
+        for (i, (png, _)) in self.frames.iter().enumerate() {
+            if png.is_animated() {
+                return Err(PngError::BadAnimation(format!("frame {i} is already animated")));
+            }
+            if png.image_header()? != header {
+                return Err(PngError::BadAnimation(format!("frame {i} header differs from frame 0")));
+            }
+            if png.chunks().iter().find(|c| c.chunk_type() == "PLTE").map(|c| c.data()) != palette {
+                return Err(PngError::BadAnimation(format!("frame {i} palette differs from frame 0")));
+            }
+        }
+
+        let mut chunks = vec![header.to_chunk()];
+        for c in first.chunks().iter().skip(1) {
+            if c.chunk_type() == "IDAT" || c.chunk_type() == &ChunkType::END_CHUNK {
+                break;
+            }
+            chunks.push(Chunk::new(c.chunk_type(), c.data().to_vec()));
+        }
+        chunks.push(AnimationControl { num_frames: self.frames.len() as u32, num_plays: self.num_plays }.to_chunk());
+
+        let mut sequence_number: u32 = 0;
+        for (i, (png, options)) in self.frames.iter().enumerate() {
+            chunks.push(FrameControl {
+                sequence_number,
+                width: header.width,
+                height: header.height,
+                x_offset: 0,
+                y_offset: 0,
+                delay_num: options.delay_num,
+                delay_den: options.delay_den,
+                dispose_op: options.dispose_op,
+                blend_op: options.blend_op,
+            }.to_chunk());
+            sequence_number += 1;
+
+            for c in png.chunks().iter().filter(|c| c.chunk_type() == "IDAT") {
+                if i == 0 {
+                    chunks.push(Chunk::new(c.chunk_type(), c.data().to_vec()));
+                }
+                else {
+                    chunks.push(FrameData { sequence_number, data: c.data().to_vec() }.to_chunk());
+                    sequence_number += 1;
+                }
+            }
+        }
+        chunks.push(Chunk::new(&ChunkType::END_CHUNK, Vec::new()));
+
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_control(sequence_number: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width: 2,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Over,
+        }
+    }
+
+    fn testing_apng() -> Png {
+        let header = crate::png::ImageHeader {
+            width: 2,
+            height: 2,
+            bit_depth: 8,
+            color_type: 6,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            AnimationControl { num_frames: 2, num_plays: 0 }.to_chunk(),
+            frame_control(0).to_chunk(),
+            Chunk::new(&ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            frame_control(1).to_chunk(),
+            FrameData { sequence_number: 2, data: vec![4, 5] }.to_chunk(),
+            FrameData { sequence_number: 3, data: vec![6] }.to_chunk(),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_frame_control_round_trip() {
+        let fc = frame_control(7);
+        assert_eq!(FrameControl::try_from(&fc.to_chunk()).unwrap(), fc);
+        assert_eq!(fc.delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_frames() {
+        let png = testing_apng();
+        let frames: Vec<Frame> = png.frames().collect::<Result<_>>().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].is_default_image);
+        assert_eq!(frames[0].data, vec![1, 2, 3]);
+        assert!(!frames[1].is_default_image);
+        assert_eq!(frames[1].data, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_validate_animation() {
+        let png = testing_apng();
+        assert!(png.validate_animation().is_ok());
+
+        let mut chunks: Vec<Chunk> = testing_apng().chunks().iter()
+            .map(|c| Chunk::new(c.chunk_type(), c.data().to_vec()))
+            .collect();
+        chunks[5] = FrameData { sequence_number: 5, data: vec![4, 5] }.to_chunk();
+        let png = Png::from_chunks(chunks);
+        assert!(matches!(png.validate_animation(), Err(PngError::BadSequence { expected: 2, found: 5 })));
+    }
+
+    #[test]
+    fn test_extract_frame() {
+        let png = testing_apng();
+        let frame = png.extract_frame(1).unwrap();
+        let types: Vec<String> = frame.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(frame.chunks()[1].data(), &[4, 5, 6]);
+    }
+
+    #[test]
+    fn test_builder() {
+        let still = |data: Vec<u8>| {
+            let mut png = testing_apng().extract_frame(0).unwrap();
+            png.replace_chunks(&ChunkType::from_str("IDAT").unwrap(),
+                               vec![Chunk::new(&ChunkType::from_str("IDAT").unwrap(), data)]);
+            png
+        };
+        let options = FrameOptions { delay_num: 1, delay_den: 2, ..FrameOptions::default() };
+        let png = ApngBuilder::new()
+            .num_plays(3)
+            .frame(still(vec![1]), FrameOptions::default())
+            .frame(still(vec![2]), options.clone())
+            .frame(still(vec![3]), options)
+            .build()
+            .unwrap();
+
+        assert!(png.validate_animation().is_ok());
+        assert_eq!(png.animation_control().unwrap(), AnimationControl { num_frames: 3, num_plays: 3 });
+        let frames: Vec<Frame> = png.frames().collect::<Result<_>>().unwrap();
+        assert_eq!(frames[2].data, vec![3]);
+        assert_eq!(frames[2].control.delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_insert_after_frame() {
+        let mut png = testing_apng();
+        png.insert_after_frame(0, Chunk::new(&ChunkType::from_str("ruSt").unwrap(), vec![0])).unwrap();
+        assert_eq!(png.chunks()[4].chunk_type(), "ruSt");
+        assert!(png.validate_animation().is_ok());
+    }
+}
@@ -5,17 +5,28 @@
 
 mod chunk_type;
 mod chunk_error;
+mod chunk_type_error;
 
 use std::fmt::{Display, Formatter};
 use std::io::Write;
+use std::ops::{Deref, DerefMut};
 use std::string::FromUtf8Error;
 use crc::Crc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 
 pub use chunk_error::ChunkError;
 pub use chunk_type::ChunkType;
+pub use chunk_type_error::ChunkTypeError;
 
 type Result<T> = std::result::Result<T, ChunkError>;
 
+/// Length of the HMAC-SHA256 tag appended by [Chunk::new_authenticated].
+pub const TAG_LENGTH: usize = 32;
+
+/// Longest chunk data allowed by the PNG specification, 2^31-1 bytes.
+pub const MAX_DATA_LENGTH: usize = i32::MAX as usize;
+
 /// Each chunk is a structured component of a PNG file.
 pub struct Chunk {
     data_length: u32,
@@ -26,16 +37,50 @@ pub struct Chunk {
 
 impl Chunk {
     /// Create a new chunk with the provided data.
+    ///
+    /// # Panics
+    /// Panics if the data is longer than [MAX_DATA_LENGTH]; use [Chunk::try_new] to get an error instead.
     pub fn new(chunk_type: &ChunkType, data: Vec<u8>) -> Chunk {
+        Chunk::try_new(chunk_type, data).expect("chunk data exceeds the maximum length")
+    }
+
+    /// Create a new chunk with the provided data, failing if the data is longer than [MAX_DATA_LENGTH].
+    pub fn try_new(chunk_type: &ChunkType, data: Vec<u8>) -> Result<Chunk> {
         let mut no_crc_chunk = Chunk {
-            data_length: data.len() as u32,
+            data_length: checked_length(data.len())?,
             chunk_type: chunk_type.clone(),
             data,
             crc: 0,
         };
 
         no_crc_chunk.crc = no_crc_chunk.crc();
-        no_crc_chunk
+        Ok(no_crc_chunk)
+    }
+
+    /// Create a new chunk whose data is followed by an HMAC-SHA256 tag over the chunk type and data,
+    /// keyed by a shared secret.
+    pub fn new_authenticated(chunk_type: &ChunkType, mut data: Vec<u8>, key: &[u8]) -> Chunk {
+        let tag = Chunk::tag_mac(chunk_type, &data, key).finalize().into_bytes();
+        data.extend_from_slice(&tag);
+        Chunk::new(chunk_type, data)
+    }
+
+    /// Verify the HMAC-SHA256 tag at the end of the data and return the data without it.
+    pub fn authenticated_data(&self, key: &[u8]) -> Result<&[u8]> {
+        let bad_tag = || ChunkError::BadTag { chunk_type: self.chunk_type.clone() };
+        if self.data.len() < TAG_LENGTH {
+            return Err(bad_tag());
+        }
+        let (data, tag) = self.data.split_at(self.data.len() - TAG_LENGTH);
+        Chunk::tag_mac(&self.chunk_type, data, key).verify_slice(tag).map_err(|_| bad_tag())?;
+        Ok(data)
+    }
+
+    fn tag_mac(chunk_type: &ChunkType, data: &[u8], key: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&chunk_type.bytes());
+        mac.update(data);
+        mac
     }
 
     /// Get the length of the data in the chunk.
@@ -48,10 +93,76 @@ impl Chunk {
         &self.chunk_type
     }
 
-    // pub fn data(&self) -> &[u8] {
-    //     let r: &[u8] = &self.data;
-    //     r
-    // }
+    /// Get the chunk data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replace the chunk data, updating the length and CRC.
+    pub fn set_data(&mut self, data: Vec<u8>) -> Result<()> {
+        self.data_length = checked_length(data.len())?;
+        self.data = data;
+        self.crc = self.crc();
+        Ok(())
+    }
+
+    /// Get mutable access to the chunk data. The length and CRC are updated by [DataMut::commit]
+    /// or when the guard is dropped.
+    pub fn data_mut(&mut self) -> DataMut<'_> {
+        DataMut { chunk: self, committed: false }
+    }
+
+    /// Parse a chunk from the start of a byte slice, keeping the stored CRC even if it does not match.
+    /// Intended for forensic work; [Chunk::try_from] rejects chunks with a bad CRC.
+    pub fn from_raw(value: &[u8]) -> Result<Chunk> {
+        //length, type, data, crc
+        if value.len() < 12 { //len, type, crc. data can be 0
+            return Err(ChunkError::TooShort { available: value.len() });
+        }
+        let data_length: u32 = u32::from_be_bytes((&value[0..4]).try_into().unwrap());
+
+        if data_length as usize > MAX_DATA_LENGTH {
+            return Err(ChunkError::DataTooLong { length: data_length as usize });
+        }
+        if data_length as usize > value.len() - 12 {
+            return Err(ChunkError::TooLong { declared: data_length, available: value.len() - 12 });
+        }
+
+        type Offsets = (usize, usize);
+        let type_offsets: Offsets = (4, 8);
+        let data_offsets: Offsets = (8, data_length as usize + 8);
+        let crc_offsets: Offsets = (data_offsets.1, data_offsets.1 + 4);
+
+        let chunk_type = ChunkType::from_raw(<[u8; 4]>::try_from(&value[type_offsets.0 .. type_offsets.1]).unwrap());
+        let data = Vec::from(&value[data_offsets.0 .. data_offsets.1]);
+        let crc = u32::from_be_bytes((&value[crc_offsets.0 .. crc_offsets.1]).try_into().unwrap());
+
+        Ok(Chunk {
+            data_length,
+            chunk_type,
+            data,
+            crc,
+        })
+    }
+
+    /// Get the CRC stored with the chunk.
+    pub fn stored_crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Test if the stored CRC matches the chunk type and data.
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc == self.crc()
+    }
+
+    /// Replace the stored CRC with the correct one. Returns the old CRC if it was wrong.
+    pub fn repair_crc(&mut self) -> Option<u32> {
+        let computed = self.crc();
+        if computed == self.crc {
+            return None;
+        }
+        Some(std::mem::replace(&mut self.crc, computed))
+    }
 
     /// Compute CRC of data and header.
     fn crc(&self) -> u32 {
@@ -77,38 +188,76 @@ impl Chunk {
     }
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = ChunkError;
+/// Convert a data length to the chunk length field, failing beyond [MAX_DATA_LENGTH].
+fn checked_length(length: usize) -> Result<u32> {
+    if length > MAX_DATA_LENGTH {
+        return Err(ChunkError::DataTooLong { length });
+    }
+    Ok(length as u32)
+}
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        //length, type, data, crc
-        if value.len() < 12 { //len, type, crc. data can be 0
-            return Err(ChunkError::TooShort);
-        }
-        let data_length: u32 = u32::from_be_bytes((&value[0..4]).try_into().unwrap());
+/// Mutable access to the data of a chunk, returned by [Chunk::data_mut].
+/// Dereferences to the data vector; the chunk length and CRC are recomputed by [DataMut::commit]
+/// or when it is dropped.
+///
+/// If the data has grown beyond [MAX_DATA_LENGTH], it is cut to that length and the chunk is left
+/// with a mismatched CRC, so it is reported as damaged; `commit` also fails in that case.
+pub struct DataMut<'a> {
+    chunk: &'a mut Chunk,
+    committed: bool,
+}
+
+impl DataMut<'_> {
+    /// Update the chunk length and CRC, failing if the data is longer than [MAX_DATA_LENGTH].
+    pub fn commit(mut self) -> Result<()> {
+        self.update(MAX_DATA_LENGTH)
+    }
 
-        if data_length > value.len() as u32 - 12 {
-            return Err(ChunkError::TooLong);
+    /// Update the chunk length and CRC once, cutting data longer than `max` bytes and leaving a
+    /// mismatched CRC in that case.
+    fn update(&mut self, max: usize) -> Result<()> {
+        self.committed = true;
+        let length = self.chunk.data.len();
+        self.chunk.data.truncate(max);
+        self.chunk.data_length = self.chunk.data.len() as u32;
+        self.chunk.crc = self.chunk.crc();
+        if length > max {
+            self.chunk.crc = !self.chunk.crc;
+            return Err(ChunkError::DataTooLong { length });
         }
+        Ok(())
+    }
+}
 
-        type Offsets = (usize, usize);
-        let type_offsets: Offsets = (4, 8);
-        let data_offsets: Offsets = (8, data_length as usize + 8);
-        let crc_offsets: Offsets = (data_offsets.1, data_offsets.1 + 4);
+impl Deref for DataMut<'_> {
+    type Target = Vec<u8>;
 
-        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&value[type_offsets.0 .. type_offsets.1]).unwrap()).unwrap();
-        let data = Vec::from(&value[data_offsets.0 .. data_offsets.1]);
-        let crc = u32::from_be_bytes((&value[crc_offsets.0 .. crc_offsets.1]).try_into().unwrap());
+    fn deref(&self) -> &Vec<u8> {
+        &self.chunk.data
+    }
+}
 
-        let chunk = Chunk {
-            data_length,
-            chunk_type,
-            data,
-            crc,
-        };
+impl DerefMut for DataMut<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.chunk.data
+    }
+}
+
+impl Drop for DataMut<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.update(MAX_DATA_LENGTH);
+        }
+    }
+}
 
-        if chunk.crc() != crc {
-            return Err(ChunkError::BadCRC);
+impl TryFrom<&[u8]> for Chunk {
+    type Error = ChunkError;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let chunk = Chunk::from_raw(value)?;
+        if !chunk.has_valid_crc() {
+            return Err(ChunkError::BadCRC { computed: chunk.crc(), stored: chunk.crc, chunk_type: chunk.chunk_type });
         }
 
         Ok(chunk)
@@ -126,6 +275,7 @@ impl Display for Chunk {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     fn testing_bytes(good: bool) -> Vec<u8> {
         let data_length: u32 = 42;
@@ -197,7 +347,7 @@ mod tests {
     fn test_invalid_chunk_from_bytes() {
         let chunk = Chunk::try_from(testing_bytes(false).as_ref());
 
-        assert!(chunk.is_err());
+        assert!(matches!(chunk, Err(ChunkError::BadCRC { stored: 2882656333, computed: 2882656334, .. })));
     }
 
     #[test]
@@ -206,6 +356,68 @@ mod tests {
 
         let _chunk_string = format!("{chunk}");
     }
+
+    #[test]
+    fn test_set_data() {
+        let mut chunk = testing_chunk();
+        chunk.set_data(b"Shorter".to_vec()).unwrap();
+        assert_eq!(chunk.length(), 7);
+        assert_eq!(chunk.crc, chunk.crc());
+
+        chunk.data_mut().extend_from_slice(b" and longer");
+        assert_eq!(chunk.length(), 18);
+        assert_eq!(chunk.as_string().unwrap(), "Shorter and longer");
+        let parsed = Chunk::try_from(chunk.as_bytes().as_slice()).unwrap();
+        assert_eq!(parsed.crc, chunk.crc);
+
+        let mut data = chunk.data_mut();
+        data.truncate(7);
+        data.commit().unwrap();
+        assert_eq!(chunk.length(), 7);
+        assert!(chunk.has_valid_crc());
+
+        let mut data = chunk.data_mut();
+        data.extend_from_slice(b" again");
+        assert!(matches!(data.update(10), Err(ChunkError::DataTooLong { length: 13 })));
+        drop(data);
+        assert_eq!((chunk.length(), chunk.data()), (10, &b"Shorter ag"[..]));
+        assert!(!chunk.has_valid_crc());
+    }
+
+    #[test]
+    fn test_raw_chunk() {
+        let mut chunk = Chunk::from_raw(testing_bytes(false).as_ref()).unwrap();
+        assert!(!chunk.has_valid_crc());
+        assert_eq!(chunk.stored_crc(), 2882656333);
+        assert_eq!(chunk.as_bytes(), testing_bytes(false));
+
+        assert_eq!(chunk.repair_crc(), Some(2882656333));
+        assert!(chunk.has_valid_crc());
+        assert_eq!(chunk.repair_crc(), None);
+        assert_eq!(chunk.as_bytes(), testing_bytes(true));
+    }
+
+    #[test]
+    fn test_length_limit() {
+        assert!(matches!(checked_length(MAX_DATA_LENGTH + 1), Err(ChunkError::DataTooLong { .. })));
+        assert_eq!(checked_length(MAX_DATA_LENGTH).unwrap(), i32::MAX as u32);
+
+        let mut bytes = testing_bytes(true);
+        bytes[0] = 0x80;
+        assert!(matches!(Chunk::try_from(bytes.as_slice()), Err(ChunkError::DataTooLong { .. })));
+    }
+
+    #[test]
+    fn test_authenticated_chunk() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::new_authenticated(&chunk_type, b"hidden".to_vec(), b"shared secret");
+        assert_eq!(chunk.length() as usize, 6 + TAG_LENGTH);
+        assert_eq!(chunk.authenticated_data(b"shared secret").unwrap(), b"hidden");
+        assert!(matches!(chunk.authenticated_data(b"wrong secret"), Err(ChunkError::BadTag { .. })));
+
+        let other_type = Chunk::new(&ChunkType::from_str("RuSs").unwrap(), chunk.data().to_vec());
+        assert!(matches!(other_type.authenticated_data(b"shared secret"), Err(ChunkError::BadTag { .. })));
+    }
 }
 
 
@@ -2,36 +2,62 @@
 
 use std::fmt::{Debug, Formatter};
 
+use super::ChunkType;
+
 /// Implements specific errors emitted by the Chunk object.
 #[derive(Debug)]
 pub enum ChunkError {
     /// Chunk CRC does not match.
-    BadCRC,
+    BadCRC {
+        /// Type of the chunk.
+        chunk_type: ChunkType,
+        /// The CRC stored in the chunk.
+        stored: u32,
+        /// The CRC computed over the chunk type and data.
+        computed: u32,
+    },
     /// Chunk length is shorter than the shortest possible chunk.
-    TooShort,
+    TooShort {
+        /// Number of bytes available.
+        available: usize,
+    },
     /// Chunk length value is longer than the actual data
-    TooLong,
-    /// Unspecified chunk error
-    GenericError,
+    TooLong {
+        /// Data length stored in the chunk.
+        declared: u32,
+        /// Number of data bytes actually available.
+        available: usize,
+    },
+    /// Chunk data is longer than the 2^31-1 bytes the specification allows.
+    DataTooLong {
+        /// Length of the data.
+        length: usize,
+    },
+    /// The HMAC tag of an authenticated chunk is missing or does not match.
+    BadTag {
+        /// Type of the chunk.
+        chunk_type: ChunkType,
+    },
 }
 
 impl std::fmt::Display for ChunkError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            ChunkError::BadCRC => write!(f, "Bad CRC."),
-            ChunkError::TooShort => write!(f, "Chunk too short."),
-            ChunkError::TooLong => write!(f, "Chunk too long."),
-            ChunkError::GenericError => write!(f, "Non-specific chunk error."),
+            ChunkError::BadCRC { chunk_type, stored, computed } => {
+                write!(f, "Bad CRC in {chunk_type} chunk: stored {stored:08X}, computed {computed:08X}.")
+            },
+            ChunkError::TooShort { available } => {
+                write!(f, "Chunk too short: {available} bytes left, at least 12 needed.")
+            },
+            ChunkError::TooLong { declared, available } => {
+                write!(f, "Chunk too long: length is {declared} but only {available} data bytes are left.")
+            },
+            ChunkError::DataTooLong { length } => {
+                write!(f, "Chunk data of {length} bytes exceeds the maximum of 2147483647.")
+            },
+            ChunkError::BadTag { chunk_type } => write!(f, "Authentication tag of {chunk_type} chunk does not match."),
         }
     }
 }
 
-impl From<()> for ChunkError {
-    fn from(_: ()) -> Self {
-        ChunkError::GenericError
-    }
-}
-
-impl std::error::Error for ChunkError {
-
-}
+impl std::error::Error for ChunkError {}
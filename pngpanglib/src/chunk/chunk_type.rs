@@ -11,6 +11,9 @@ use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
+use super::ChunkTypeError;
+use crate::registry;
+
 
 impl ChunkType {
     /// The type code for an end chunk.
@@ -21,9 +24,16 @@ impl ChunkType {
         self.type_code
     }
 
-    /// Test if the type code is valid.
+    /// Create a type code from any four bytes, without validation.
+    /// Intended for forensic parsing, where files may carry type codes that break the rules;
+    /// use [ChunkType::try_from] for type codes that will be written.
+    pub fn from_raw(type_code: [u8; 4]) -> ChunkType {
+        ChunkType { type_code }
+    }
+
+    /// Test if the type code is valid: four ASCII letters with the reserved bit clear.
     pub fn is_valid(&self) -> bool {
-        self.type_code[2] & 0b100000 == 0
+        self.type_code.iter().all(u8::is_ascii_alphabetic) && self.is_reserved_bit_valid()
     }
 
     /// Test if the type code is flagged critical.
@@ -45,30 +55,48 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         self.type_code[3] & 0b100000 != 0
     }
+
+    /// Check that a chunk of this type can be added to a file without breaking it for decoders that
+    /// do not know the type: it must be ancillary and have the reserved bit clear.
+    pub fn check_addable(&self) -> Result<(), ChunkTypeError> {
+        if self.is_critical() {
+            Err(ChunkTypeError::Critical { chunk_type: self.to_string() })
+        }
+        else if !self.is_reserved_bit_valid() {
+            Err(ChunkTypeError::ReservedBitSet { chunk_type: self.to_string() })
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    /// Test if the type code is one defined by the PNG specification or its registered extensions.
+    /// See [crate::registry] for what is known about each type.
+    pub fn is_standard(&self) -> bool {
+        registry::lookup(self).is_some_and(|info| info.is_registered())
+    }
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = ();
+    type Error = ChunkTypeError;
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
-        Ok(ChunkType { type_code: value })
+        match value.iter().position(|b| !b.is_ascii_alphabetic()) {
+            Some(position) => Err(ChunkTypeError::BadByte {
+                input: value.escape_ascii().to_string(),
+                byte: value[position],
+                position,
+            }),
+            None => Ok(ChunkType { type_code: value }),
+        }
     }
 }
 
 impl FromStr for ChunkType {
-    type Err = ();
+    type Err = ChunkTypeError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let bytes = s.as_bytes();
-        if bytes.len() != 4 {
-            Err(())
-        }
-        else {
-            for &b in bytes {
-                if !((65..=90).contains(&b) || (97..=122).contains(&b)) {
-                    return Err(());
-                }
-            }
-            Ok(ChunkType { type_code: [bytes[0], bytes[1], bytes[2], bytes[3]] })
-        }
+        let bytes: [u8; 4] = s.as_bytes().try_into()
+            .map_err(|_| ChunkTypeError::BadLength { input: s.to_string(), length: s.len() })?;
+        ChunkType::try_from(bytes)
     }
 }
 
@@ -181,10 +209,35 @@ mod tests {
         assert!(!chunk.is_valid());
 
         let chunk = ChunkType::from_str("Ru1t");
-        assert!(chunk.is_err());
+        assert_eq!(chunk, Err(ChunkTypeError::BadByte { input: "Ru1t".to_string(), byte: b'1', position: 2 }));
+
+        let chunk = ChunkType::from_str("RuStt");
+        assert_eq!(chunk, Err(ChunkTypeError::BadLength { input: "RuStt".to_string(), length: 5 }));
 
     }
 
+    #[test]
+    pub fn test_chunk_type_strict_and_raw() {
+        assert_eq!(ChunkType::try_from([82, 117, 0, 116]),
+                   Err(ChunkTypeError::BadByte { input: "Ru\\x00t".to_string(), byte: 0, position: 2 }));
+        let raw = ChunkType::from_raw([82, 117, 0, 116]);
+        assert_eq!(raw.bytes(), [82, 117, 0, 116]);
+        assert!(!raw.is_valid());
+    }
+
+    #[test]
+    pub fn test_chunk_type_check_addable() {
+        assert!(ChunkType::from_str("ruSt").unwrap().check_addable().is_ok());
+        assert!(matches!(ChunkType::from_str("RuSt").unwrap().check_addable(), Err(ChunkTypeError::Critical { .. })));
+        assert!(matches!(ChunkType::from_str("rust").unwrap().check_addable(), Err(ChunkTypeError::ReservedBitSet { .. })));
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("gAMA").unwrap().is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -0,0 +1,57 @@
+//! This is an implementation of chunk type code errors.
+
+use std::fmt::{Debug, Formatter};
+
+/// Implements specific errors emitted when parsing a chunk type code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkTypeError {
+    /// The type code is not exactly four bytes long.
+    BadLength {
+        /// The offending input.
+        input: String,
+        /// Its length in bytes.
+        length: usize,
+    },
+    /// The type code contains a byte that is not an ASCII letter.
+    BadByte {
+        /// The offending input, with non-printable bytes escaped.
+        input: String,
+        /// The offending byte.
+        byte: u8,
+        /// Position of the byte within the type code.
+        position: usize,
+    },
+    /// The type code is flagged critical, so decoders that do not know it will reject the file.
+    Critical {
+        /// The offending type code.
+        chunk_type: String,
+    },
+    /// The reserved bit of the type code is set, which no conforming file may do.
+    ReservedBitSet {
+        /// The offending type code.
+        chunk_type: String,
+    },
+}
+
+impl std::fmt::Display for ChunkTypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkTypeError::BadLength { input, length } => {
+                write!(f, "Chunk type \"{input}\" is {length} bytes long; chunk types are 4 ASCII letters.")
+            },
+            ChunkTypeError::BadByte { input, byte, position } => {
+                write!(f, "Chunk type \"{input}\" has byte 0x{byte:02X} at position {position}; chunk types are 4 ASCII letters.")
+            },
+            ChunkTypeError::Critical { chunk_type } => {
+                write!(f, "Chunk type {chunk_type} is critical: its first letter is uppercase, so decoders that do not \
+                           know it will reject the file. Use a lowercase first letter.")
+            },
+            ChunkTypeError::ReservedBitSet { chunk_type } => {
+                write!(f, "Chunk type {chunk_type} sets the reserved bit: its third letter is lowercase, which the \
+                           PNG specification forbids. Use an uppercase third letter.")
+            },
+        }
+    }
+}
+
+impl std::error::Error for ChunkTypeError {}
@@ -0,0 +1,409 @@
+//! Typed access to chunk contents.
+//!
+//! A [ChunkCodec] converts between a chunk and a Rust value. Implementations are provided for
+//! several standard chunks; other crates implement the trait for their own chunk formats and get
+//! the same [Png::get] and [Png::set] helpers as the built-in types.
+//!
+//! ```
+//! use pngpanglib::chunk::Chunk;
+//! use pngpanglib::codec::ChunkCodec;
+//! use pngpanglib::png::PngError;
+//!
+//! struct Revision(u32);
+//!
+//! impl ChunkCodec for Revision {
+//!     const CHUNK_TYPE: &'static str = "reVn";
+//!
+//!     fn decode(chunk: &Chunk) -> Result<Self, PngError> {
+//!         let data: [u8; 4] = chunk.data().try_into()
+//!             .map_err(|_| PngError::MalformedChunk(Self::CHUNK_TYPE.to_string()))?;
+//!         Ok(Revision(u32::from_be_bytes(data)))
+//!     }
+//!
+//!     fn encode(&self) -> Chunk {
+//!         Chunk::new(&Self::chunk_type(), self.0.to_be_bytes().to_vec())
+//!     }
+//! }
+//! ```
+
+use std::str::FromStr;
+
+use crate::apng::{AnimationControl, FrameControl, FrameData, ACTL, FCTL, FDAT};
+use crate::chunk::{Chunk, ChunkType};
+use crate::png::{ImageHeader, Png, PngError};
+use crate::registry::{self, Multiplicity, Placement};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Conversion between a chunk and a typed value.
+pub trait ChunkCodec: Sized {
+    /// The type code of the chunk holding the value.
+    const CHUNK_TYPE: &'static str;
+
+    /// Decode the value from a chunk.
+    fn decode(chunk: &Chunk) -> Result<Self>;
+
+    /// Encode the value as a chunk.
+    fn encode(&self) -> Chunk;
+
+    /// Get the chunk type of the value.
+    fn chunk_type() -> ChunkType {
+        ChunkType::from_str(Self::CHUNK_TYPE).expect("codec chunk types are valid")
+    }
+}
+
+/// Get the data of a chunk after checking its type and length.
+fn checked_data<T: ChunkCodec>(chunk: &Chunk, len: usize) -> Result<&[u8]> {
+    let data = chunk.data();
+    if chunk.chunk_type() != T::CHUNK_TYPE || data.len() != len {
+        return Err(PngError::MalformedChunk(chunk.chunk_type().to_string()));
+    }
+    Ok(data)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+impl ChunkCodec for ImageHeader {
+    const CHUNK_TYPE: &'static str = ImageHeader::CHUNK_TYPE;
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        ImageHeader::try_from(chunk)
+    }
+
+    fn encode(&self) -> Chunk {
+        self.to_chunk()
+    }
+}
+
+impl ChunkCodec for AnimationControl {
+    const CHUNK_TYPE: &'static str = ACTL;
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        AnimationControl::try_from(chunk)
+    }
+
+    fn encode(&self) -> Chunk {
+        self.to_chunk()
+    }
+}
+
+impl ChunkCodec for FrameControl {
+    const CHUNK_TYPE: &'static str = FCTL;
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        FrameControl::try_from(chunk)
+    }
+
+    fn encode(&self) -> Chunk {
+        self.to_chunk()
+    }
+}
+
+impl ChunkCodec for FrameData {
+    const CHUNK_TYPE: &'static str = FDAT;
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        FrameData::try_from(chunk)
+    }
+
+    fn encode(&self) -> Chunk {
+        self.to_chunk()
+    }
+}
+
+/// Contents of the gAMA chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gamma {
+    /// Gamma of the encoding times 100000.
+    pub gamma: u32,
+}
+
+impl ChunkCodec for Gamma {
+    const CHUNK_TYPE: &'static str = "gAMA";
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        let data = checked_data::<Self>(chunk, 4)?;
+        Ok(Gamma { gamma: be_u32(data) })
+    }
+
+    fn encode(&self) -> Chunk {
+        Chunk::new(&Self::chunk_type(), self.gamma.to_be_bytes().to_vec())
+    }
+}
+
+/// Contents of the sRGB chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StandardRgb {
+    /// Rendering intent: 0 perceptual, 1 relative colorimetric, 2 saturation, 3 absolute colorimetric.
+    pub rendering_intent: u8,
+}
+
+impl ChunkCodec for StandardRgb {
+    const CHUNK_TYPE: &'static str = "sRGB";
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        let data = checked_data::<Self>(chunk, 1)?;
+        if data[0] > 3 {
+            return Err(PngError::MalformedChunk(Self::CHUNK_TYPE.to_string()));
+        }
+        Ok(StandardRgb { rendering_intent: data[0] })
+    }
+
+    fn encode(&self) -> Chunk {
+        Chunk::new(&Self::chunk_type(), vec![self.rendering_intent])
+    }
+}
+
+/// Contents of the pHYs chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysicalDimensions {
+    /// Pixels per unit along the x axis.
+    pub x: u32,
+    /// Pixels per unit along the y axis.
+    pub y: u32,
+    /// Unit: 0 for an aspect ratio only, 1 for the metre.
+    pub unit: u8,
+}
+
+impl ChunkCodec for PhysicalDimensions {
+    const CHUNK_TYPE: &'static str = "pHYs";
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        let data = checked_data::<Self>(chunk, 9)?;
+        Ok(PhysicalDimensions { x: be_u32(&data[0..4]), y: be_u32(&data[4..8]), unit: data[8] })
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data = self.x.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.y.to_be_bytes());
+        data.push(self.unit);
+        Chunk::new(&Self::chunk_type(), data)
+    }
+}
+
+/// Contents of the tIME chunk, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastModified {
+    /// Full year, e.g. 2024.
+    pub year: u16,
+    /// Month, 1 to 12.
+    pub month: u8,
+    /// Day, 1 to 31.
+    pub day: u8,
+    /// Hour, 0 to 23.
+    pub hour: u8,
+    /// Minute, 0 to 59.
+    pub minute: u8,
+    /// Second, 0 to 60 to allow for leap seconds.
+    pub second: u8,
+}
+
+impl ChunkCodec for LastModified {
+    const CHUNK_TYPE: &'static str = "tIME";
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        let data = checked_data::<Self>(chunk, 7)?;
+        Ok(LastModified {
+            year: u16::from_be_bytes([data[0], data[1]]),
+            month: data[2],
+            day: data[3],
+            hour: data[4],
+            minute: data[5],
+            second: data[6],
+        })
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data = self.year.to_be_bytes().to_vec();
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+        Chunk::new(&Self::chunk_type(), data)
+    }
+}
+
+impl Png {
+    /// Decode the first chunk of a type.
+    pub fn get<T: ChunkCodec>(&self) -> Result<T> {
+        let chunk_type = T::chunk_type();
+        match self.chunk_by_type(&chunk_type) {
+            Some(c) => T::decode(c),
+            None => Err(PngError::ChunkNotFound(chunk_type)),
+        }
+    }
+
+    /// Decode every chunk of a type, in file order.
+    pub fn get_all<T: ChunkCodec>(&self) -> Result<Vec<T>> {
+        let chunk_type = T::chunk_type();
+        self.chunks().iter()
+            .filter(|c| *c.chunk_type() == chunk_type)
+            .map(T::decode)
+            .collect()
+    }
+
+    /// Encode a value and store it in place of the chunk of its type.
+    /// For types that occur at most once, every chunk of the type is replaced. For types the chunk
+    /// registry allows several of, such as fcTL, only the first is replaced; use [Png::set_nth] and
+    /// [Png::push] for the others. If there were none, the chunk is placed where the chunk registry
+    /// says it belongs, or appended.
+    /// As with [Png::replace_chunks], returns the chunks dropped under the copy policy.
+    pub fn set<T: ChunkCodec>(&mut self, value: &T) -> Vec<Chunk> {
+        let chunk_type = T::chunk_type();
+        let single = registry::lookup(&chunk_type).is_none_or(|i| i.multiplicity == Multiplicity::Once);
+        if self.chunk_by_type(&chunk_type).is_none() {
+            self.place(value.encode())
+        }
+        else if single {
+            self.replace_chunks(&chunk_type, vec![value.encode()])
+        }
+        else {
+            self.set_nth(0, value).expect("a chunk of the type exists and is not the end chunk")
+        }
+    }
+
+    /// Encode a value and replace the `n`th chunk of its type with it.
+    /// Fails if there are not that many chunks of the type.
+    pub fn set_nth<T: ChunkCodec>(&mut self, n: usize, value: &T) -> Result<Vec<Chunk>> {
+        let chunk_type = T::chunk_type();
+        let index = self.chunks().iter().enumerate()
+            .filter(|(_, c)| *c.chunk_type() == chunk_type)
+            .nth(n)
+            .map(|(i, _)| i)
+            .ok_or(PngError::ChunkNotFound(chunk_type))?;
+        Ok(self.replace(index, value.encode())?.1)
+    }
+
+    /// Encode a value and add it after the last chunk of its type, or where the chunk registry says
+    /// it belongs if there is none, keeping the existing chunks of the type.
+    pub fn push<T: ChunkCodec>(&mut self, value: &T) -> Result<Vec<Chunk>> {
+        let chunk_type = T::chunk_type();
+        if self.chunk_by_type(&chunk_type).is_some() {
+            self.insert_after(&chunk_type, value.encode())
+        }
+        else {
+            Ok(self.place(value.encode()))
+        }
+    }
+
+    /// Add a chunk of a type not yet in the file where the chunk registry says it belongs, or append it.
+    fn place(&mut self, chunk: Chunk) -> Vec<Chunk> {
+        let chunk_type = chunk.chunk_type().clone();
+        let position = |t: &str| self.chunks().iter().position(|c| *c.chunk_type() == *t);
+        let before = match registry::lookup(&chunk_type).map(|i| i.placement) {
+            Some(Placement::BeforePlte) => position("PLTE").or(position("IDAT")),
+            Some(Placement::AfterPlte | Placement::BeforeIdat) => position("IDAT"),
+            _ => None,
+        };
+        match before {
+            Some(index) => self.insert_chunk(index, chunk),
+            None => self.append_chunk(chunk),
+        }
+        if chunk_type.is_critical() {
+            self.drop_unsafe_to_copy()
+        }
+        else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::{BlendOp, DisposeOp};
+
+    struct Revision(u32);
+
+    impl ChunkCodec for Revision {
+        const CHUNK_TYPE: &'static str = "reVn";
+
+        fn decode(chunk: &Chunk) -> Result<Self> {
+            Ok(Revision(be_u32(checked_data::<Self>(chunk, 4)?)))
+        }
+
+        fn encode(&self) -> Chunk {
+            Chunk::new(&Self::chunk_type(), self.0.to_be_bytes().to_vec())
+        }
+    }
+
+    fn testing_png() -> Png {
+        let header = ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: 0,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        Png::from_chunks(vec![
+            header.encode(),
+            Chunk::new(&ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ])
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_get_and_set_builtin() {
+        let mut png = testing_png();
+        assert_eq!(png.get::<ImageHeader>().unwrap().width, 1);
+        assert!(matches!(png.get::<Gamma>(), Err(PngError::ChunkNotFound(_))));
+
+        png.set(&Gamma { gamma: 45455 });
+        png.set(&LastModified { year: 2024, month: 2, day: 29, hour: 12, minute: 0, second: 0 });
+        png.set(&Gamma { gamma: 100000 });
+        assert_eq!(types(&png), vec!["IHDR", "gAMA", "IDAT", "tIME", "IEND"]);
+        assert_eq!(png.get::<Gamma>().unwrap(), Gamma { gamma: 100000 });
+        assert_eq!(png.get::<LastModified>().unwrap().day, 29);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let phys = PhysicalDimensions { x: 2835, y: 2835, unit: 1 };
+        assert_eq!(PhysicalDimensions::decode(&phys.encode()).unwrap(), phys);
+        let srgb = StandardRgb { rendering_intent: 0 };
+        assert_eq!(StandardRgb::decode(&srgb.encode()).unwrap(), srgb);
+        assert!(StandardRgb::decode(&Chunk::new(&ChunkType::from_str("sRGB").unwrap(), vec![4])).is_err());
+        assert!(Gamma::decode(&srgb.encode()).is_err());
+    }
+
+    #[test]
+    fn test_multiple_instances() {
+        let frame = |sequence_number| FrameControl {
+            sequence_number,
+            width: 1,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        let mut png = testing_png();
+        png.set(&frame(0));
+        png.push(&frame(1)).unwrap();
+        png.push(&frame(2)).unwrap();
+        png.set(&frame(10));
+        png.set_nth(2, &frame(12)).unwrap();
+        assert!(matches!(png.set_nth(3, &frame(13)), Err(PngError::ChunkNotFound(_))));
+
+        let sequence: Vec<u32> = png.get_all::<FrameControl>().unwrap().iter().map(|f| f.sequence_number).collect();
+        assert_eq!(sequence, vec![10, 1, 12]);
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "fcTL", "fcTL", "fcTL", "IEND"]);
+    }
+
+    #[test]
+    fn test_custom_codec() {
+        let mut png = testing_png();
+        png.set(&Revision(7));
+        png.set(&Revision(8));
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "reVn", "IEND"]);
+        assert_eq!(png.get::<Revision>().unwrap().0, 8);
+        assert_eq!(png.get_all::<Revision>().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+//! Pixel-level comparison of two PNG files.
+//!
+//! Both images are decoded and every pixel is normalized to 16-bit RGBA, so files that store the
+//! same picture with different color types, bit depths, palettes, filters or compression compare
+//! as identical. Differences are measured per channel on the 0 to 65535 scale.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::image::{EncodeOptions, ImageData};
+use crate::png::{ImageHeader, Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Largest channel value after normalization.
+const MAX_CHANNEL: f64 = 65535.0;
+/// Darkest red used for a differing pixel in the visualization; the pixels with the largest error are full red.
+const MIN_HIGHLIGHT: u64 = 96;
+/// Divisor applied to the brightness of unchanged pixels in the visualization.
+const DIM: u8 = 4;
+
+/// Result of comparing the pixels of two images of the same size.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// Width of both images.
+    pub width: u32,
+    /// Height of both images.
+    pub height: u32,
+    /// Number of pixels with at least one differing channel.
+    pub differing_pixels: usize,
+    /// Largest difference of any channel.
+    pub max_error: u16,
+    /// Mean difference over all channels of all pixels.
+    pub mean_error: f64,
+    /// Peak signal-to-noise ratio in decibels, or None if the images are identical.
+    pub psnr: Option<f64>,
+    /// Largest channel difference of each pixel.
+    pixel_errors: Vec<u16>,
+    /// Dimmed gray of each pixel of the old image.
+    background: Vec<u8>,
+}
+
+impl Comparison {
+    /// Test if every pixel is the same after normalization.
+    pub fn is_identical(&self) -> bool {
+        self.differing_pixels == 0
+    }
+
+    /// Render the differences as an RGB image: unchanged pixels are a dimmed gray version of the
+    /// old image and differing pixels are red, brighter for larger errors.
+    pub fn visualize(&self) -> Result<Png> {
+        let header = ImageHeader {
+            width: self.width,
+            height: self.height,
+            bit_depth: 8,
+            color_type: 2,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let max = self.max_error.max(1) as u64;
+        let data = self.pixel_errors.iter().zip(&self.background)
+            .flat_map(|(&error, &gray)| match error {
+                0 => [gray; 3],
+                e => [(MIN_HIGHLIGHT + (255 - MIN_HIGHLIGHT) * e as u64 / max) as u8, 0, 0],
+            })
+            .collect();
+        let stream = ImageData::new(header.clone(), data)?.encode(&EncodeOptions::default())?;
+
+        Ok(Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(&ChunkType::from_str("IDAT").unwrap(), stream),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ]))
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (width, height) = (self.width, self.height);
+        if self.is_identical() {
+            return write!(f, "Pixel data is identical ({width}x{height}).");
+        }
+        let total = self.pixel_errors.len();
+        writeln!(f, "Pixel data differs ({width}x{height}):")?;
+        writeln!(f, "  differing pixels: {} of {total} ({:.2}%)",
+                 self.differing_pixels, 100.0 * self.differing_pixels as f64 / total as f64)?;
+        writeln!(f, "  max channel error: {} of 65535", self.max_error)?;
+        writeln!(f, "  mean channel error: {:.3}", self.mean_error)?;
+        write!(f, "  PSNR: {:.2} dB", self.psnr.unwrap_or(f64::INFINITY))
+    }
+}
+
+/// Compare the decoded pixels of two images.
+/// Fails if either image cannot be decoded or the sizes differ.
+pub fn compare(old: &Png, new: &Png) -> Result<Comparison> {
+    let (old_header, new_header) = (old.image_header()?, new.image_header()?);
+    if (old_header.width, old_header.height) != (new_header.width, new_header.height) {
+        return Err(PngError::Unsupported(format!("comparing images of different sizes, {}x{} and {}x{}",
+            old_header.width, old_header.height, new_header.width, new_header.height)));
+    }
+    let (old_pixels, new_pixels) = (old.decode_rgba16()?, new.decode_rgba16()?);
+
+    let mut pixel_errors = Vec::<u16>::with_capacity(old_pixels.len());
+    let mut background = Vec::<u8>::with_capacity(old_pixels.len());
+    let (mut sum, mut sum_squares) = (0u64, 0f64);
+    for (o, n) in old_pixels.iter().zip(&new_pixels) {
+        let errors = [0, 1, 2, 3].map(|i| o[i].abs_diff(n[i]));
+        sum += errors.iter().map(|&e| e as u64).sum::<u64>();
+        sum_squares += errors.iter().map(|&e| (e as f64).powi(2)).sum::<f64>();
+        pixel_errors.push(*errors.iter().max().unwrap());
+
+        // Rec. 601 luma, darkened by transparency, reduced to 8 bits
+        let luma = (299 * o[0] as u64 + 587 * o[1] as u64 + 114 * o[2] as u64) / 1000;
+        background.push(((luma * o[3] as u64 / 65535) >> 8) as u8 / DIM);
+    }
+
+    let channels = (4 * pixel_errors.len()).max(1) as f64;
+    let mse = sum_squares / channels;
+    Ok(Comparison {
+        width: old_header.width,
+        height: old_header.height,
+        differing_pixels: pixel_errors.iter().filter(|&&e| e > 0).count(),
+        max_error: pixel_errors.iter().copied().max().unwrap_or(0),
+        mean_error: sum as f64 / channels,
+        psnr: (mse > 0.0).then(|| 10.0 * (MAX_CHANNEL * MAX_CHANNEL / mse).log10()),
+        pixel_errors,
+        background,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::FilterStrategy;
+
+    fn testing_png(bit_depth: u8, color_type: u8, data: Vec<u8>) -> Png {
+        let header = ImageHeader {
+            width: 2,
+            height: 2,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let mut png = Png::from_chunks(vec![header.to_chunk(), Chunk::new(&ChunkType::END_CHUNK, Vec::new())]);
+        let options = EncodeOptions { filter: FilterStrategy::Paeth, compression: 9 };
+        png.set_image(&ImageData::new(header, data).unwrap(), &options).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_identical_across_formats() {
+        let rgb = testing_png(8, 2, vec![0, 0, 0, 255, 255, 255, 17, 17, 17, 255, 255, 255]);
+        let rgba16 = testing_png(16, 6, [0, 255, 17, 255].iter()
+            .flat_map(|&v| [v, v, v, v, v, v, 255, 255])
+            .collect());
+        let gray = testing_png(8, 0, vec![0, 255, 17, 255]);
+
+        let comparison = compare(&rgb, &rgba16).unwrap();
+        assert!(comparison.is_identical());
+        assert_eq!(comparison.psnr, None);
+        assert!(compare(&rgb, &gray).unwrap().is_identical());
+        assert_eq!(comparison.to_string(), "Pixel data is identical (2x2).");
+    }
+
+    #[test]
+    fn test_differences() {
+        let old = testing_png(8, 0, vec![0, 100, 200, 255]);
+        let new = testing_png(8, 0, vec![0, 101, 200, 245]);
+        let comparison = compare(&old, &new).unwrap();
+        assert_eq!(comparison.differing_pixels, 2);
+        assert_eq!(comparison.max_error, 10 * 257);
+        assert!((comparison.mean_error - (3.0 * 11.0 * 257.0) / 16.0).abs() < 1e-9);
+        assert!(comparison.psnr.unwrap() > 20.0);
+
+        let visual = comparison.visualize().unwrap();
+        let pixels = visual.decode_rgba16().unwrap();
+        assert_eq!(pixels[0], [0, 0, 0, 65535]);
+        assert_eq!(pixels[3], [65535, 0, 0, 65535]);
+        assert!(pixels[1][0] < 65535 && pixels[1][1] == 0);
+        assert_eq!(pixels[2][0], pixels[2][1]);
+
+        let small = Png::from_chunks(vec![
+            ImageHeader { width: 1, ..old.image_header().unwrap() }.to_chunk(),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ]);
+        assert!(matches!(compare(&old, &small), Err(PngError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,403 @@
+//! Chunk-level comparison of two PNG files.
+//!
+//! The chunks of both files are aligned in order, pairing chunks of the same type and preferring
+//! identical ones, much like a line diff. Paired chunks with different data are modified; unpaired
+//! chunks are removed or added, and a removed chunk that reappears unchanged elsewhere is reported
+//! as moved. Chunks are compared by a hash of their data, and long runs of differing chunks
+//! are paired by type and order instead of aligned. Modified chunks of well-known types are
+//! described field by field, small ones byte by byte.
+
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+
+use crate::apng::{AnimationControl, FrameControl};
+use crate::chunk::Chunk;
+use crate::codec::{ChunkCodec, Gamma, LastModified, PhysicalDimensions, StandardRgb};
+use crate::png::{ImageHeader, Png};
+use crate::scan::text_content;
+
+/// Chunks with at most this many bytes on both sides are compared byte by byte.
+const SMALL_PAYLOAD: usize = 64;
+/// Alignment score of two identical chunks. Chunks are aligned to maximize the total score, so
+/// chunks of the same type stay paired in order and identical ones are preferred among them.
+const IDENTICAL_SCORE: u32 = 3;
+/// Alignment score of two chunks of the same type with different data.
+const SAME_TYPE_SCORE: u32 = 2;
+/// Largest alignment table built; longer runs of differing chunks are aligned by type and order.
+const MAX_ALIGN_CELLS: usize = 1 << 22;
+/// Longest text shown for a changed text field.
+const MAX_TEXT_SHOWN: usize = 60;
+
+/// How a chunk differs between the two files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Only in the new file.
+    Added,
+    /// Only in the old file.
+    Removed,
+    /// Identical in both files, at a different place among the other chunks.
+    Moved,
+    /// Same type, different data.
+    Modified,
+}
+
+/// A single difference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkChange {
+    /// What changed.
+    pub kind: ChangeKind,
+    /// Type of the chunk.
+    pub chunk_type: String,
+    /// Position in the old file, if the chunk is there.
+    pub old_index: Option<usize>,
+    /// Position in the new file, if the chunk is there.
+    pub new_index: Option<usize>,
+    /// Field or byte differences of a modified chunk.
+    pub details: Vec<String>,
+}
+
+impl Display for ChunkChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let place = match (self.old_index, self.new_index) {
+            (Some(o), Some(n)) => format!("chunk {o} -> {n}"),
+            (Some(i), None) | (None, Some(i)) => format!("chunk {i}"),
+            (None, None) => String::new(),
+        };
+        write!(f, "{:<8} {place} ({})", format!("{:?}", self.kind), self.chunk_type)?;
+        for detail in &self.details {
+            write!(f, "\n    {detail}")?;
+        }
+        Ok(())
+    }
+}
+
+/// All differences between two files, in file order.
+#[derive(Debug, Clone, Default)]
+pub struct DiffReport {
+    /// The chunk differences.
+    pub changes: Vec<ChunkChange>,
+    /// Description of a difference in the data after the end chunk, if any.
+    pub trailing: Option<String>,
+}
+
+impl DiffReport {
+    /// Test if the files have the same chunks in the same order and the same trailing data.
+    pub fn is_identical(&self) -> bool {
+        self.changes.is_empty() && self.trailing.is_none()
+    }
+}
+
+impl Display for DiffReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_identical() {
+            return write!(f, "No chunk differences.");
+        }
+        let mut lines = self.changes.iter().map(|c| c.to_string()).collect::<Vec<String>>();
+        lines.extend(self.trailing.iter().map(|t| format!("Trailing data: {t}")));
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Compare two PNGs chunk by chunk.
+pub fn diff(old: &Png, new: &Png) -> DiffReport {
+    let (a, b) = (old.chunks(), new.chunks());
+    let (keys_a, keys_b) = (a.iter().map(key).collect::<Vec<Key>>(), b.iter().map(key).collect::<Vec<Key>>());
+
+    let change = |kind, chunk: &Chunk, old_index, new_index| ChunkChange {
+        kind,
+        chunk_type: chunk.chunk_type().to_string(),
+        old_index,
+        new_index,
+        details: Vec::new(),
+    };
+    let mut changes = Vec::<ChunkChange>::new();
+    for step in align(&keys_a, &keys_b) {
+        match step {
+            (Some(i), Some(j)) if keys_a[i] != keys_b[j] => {
+                let mut modified = change(ChangeKind::Modified, &a[i], Some(i), Some(j));
+                modified.details = chunk_details(&a[i], &b[j]);
+                changes.push(modified);
+            }
+            (Some(i), None) => changes.push(change(ChangeKind::Removed, &a[i], Some(i), None)),
+            (None, Some(j)) => changes.push(change(ChangeKind::Added, &b[j], None, Some(j))),
+            _ => (),
+        }
+    }
+
+    // A removed chunk that was added unchanged elsewhere has moved
+    let mut added = HashMap::<Key, VecDeque<usize>>::new();
+    for (p, c) in changes.iter().enumerate().filter(|(_, c)| c.kind == ChangeKind::Added) {
+        added.entry(keys_b[c.new_index.unwrap()]).or_default().push_back(p);
+    }
+    let mut taken = vec![false; changes.len()];
+    for k in 0..changes.len() {
+        if changes[k].kind == ChangeKind::Removed {
+            if let Some(p) = added.get_mut(&keys_a[changes[k].old_index.unwrap()]).and_then(|q| q.pop_front()) {
+                taken[p] = true;
+                changes[k].kind = ChangeKind::Moved;
+                changes[k].new_index = changes[p].new_index;
+            }
+        }
+    }
+    let mut taken = taken.into_iter();
+    changes.retain(|_| !taken.next().unwrap());
+
+    let trailing = match (old.trailing_data(), new.trailing_data()) {
+        (o, n) if o == n => None,
+        (o, n) => Some(format!("{} bytes -> {} bytes", o.len(), n.len())),
+    };
+    DiffReport { changes, trailing }
+}
+
+/// Type code, and length and hash of the data of a chunk, so chunks are compared without their data.
+type Key = ([u8; 4], (usize, u64));
+
+/// A step of an alignment: a pair of chunks, or a chunk in only one of the sequences.
+pub(crate) type Step = (Option<usize>, Option<usize>);
+
+fn key(chunk: &Chunk) -> Key {
+    let mut hasher = DefaultHasher::new();
+    chunk.data().hash(&mut hasher);
+    (chunk.chunk_type().bytes(), (chunk.data().len(), hasher.finish()))
+}
+
+/// Align two sequences of chunks, each given by its type code and a fingerprint of its data,
+/// pairing chunks of the same type and preferring identical ones. The common start and end are
+/// paired directly; the rest is aligned exactly if the table fits in [MAX_ALIGN_CELLS], otherwise
+/// by type and order of occurrence.
+pub(crate) fn align<T: PartialEq>(a: &[([u8; 4], T)], b: &[([u8; 4], T)]) -> Vec<Step> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (middle_a, middle_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let middle = match (middle_a.len() + 1).saturating_mul(middle_b.len() + 1) {
+        cells if cells <= MAX_ALIGN_CELLS => align_exact(middle_a, middle_b),
+        _ => align_by_type(middle_a, middle_b),
+    };
+
+    let mut steps: Vec<Step> = (0..prefix).map(|k| (Some(k), Some(k))).collect();
+    steps.extend(middle.into_iter().map(|(i, j)| (i.map(|i| i + prefix), j.map(|j| j + prefix))));
+    steps.extend((0..suffix).map(|k| (Some(a.len() - suffix + k), Some(b.len() - suffix + k))));
+    steps
+}
+
+/// Align to maximize the total score, like a line diff.
+fn align_exact<T: PartialEq>(a: &[([u8; 4], T)], b: &[([u8; 4], T)]) -> Vec<Step> {
+    let (n, m) = (a.len(), b.len());
+    let score = |i: usize, j: usize| match (a[i].0 == b[j].0, a[i] == b[j]) {
+        (true, true) => IDENTICAL_SCORE,
+        (true, false) => SAME_TYPE_SCORE,
+        (false, _) => 0,
+    };
+    let width = m + 1;
+    let mut best = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            let skip = best[(i + 1) * width + j].max(best[i * width + j + 1]);
+            best[i * width + j] = match score(i, j) {
+                0 => skip,
+                s => skip.max(s + best[(i + 1) * width + j + 1]),
+            };
+        }
+    }
+
+    let mut steps = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let s = if i < n && j < m { score(i, j) } else { 0 };
+        if s > 0 && best[i * width + j] == s + best[(i + 1) * width + j + 1] {
+            steps.push((Some(i), Some(j)));
+            i += 1;
+            j += 1;
+        }
+        else if j >= m || (i < n && best[(i + 1) * width + j] >= best[i * width + j + 1]) {
+            steps.push((Some(i), None));
+            i += 1;
+        }
+        else {
+            steps.push((None, Some(j)));
+            j += 1;
+        }
+    }
+    steps
+}
+
+/// Pair the k-th chunk of each type in one sequence with the k-th chunk of that type in the other,
+/// in linear time. Unpaired chunks of the new sequence come last.
+fn align_by_type<T>(a: &[([u8; 4], T)], b: &[([u8; 4], T)]) -> Vec<Step> {
+    let mut positions = HashMap::<[u8; 4], VecDeque<usize>>::new();
+    for (j, k) in b.iter().enumerate() {
+        positions.entry(k.0).or_default().push_back(j);
+    }
+    let mut steps: Vec<Step> = a.iter().enumerate()
+        .map(|(i, k)| (Some(i), positions.get_mut(&k.0).and_then(|q| q.pop_front())))
+        .collect();
+    let mut unpaired: Vec<usize> = positions.into_values().flatten().collect();
+    unpaired.sort_unstable();
+    steps.extend(unpaired.into_iter().map(|j| (None, Some(j))));
+    steps
+}
+
+/// Describe how two chunks of the same type differ: by decoded field for well-known types, byte by
+/// byte for small chunks and by a summary otherwise.
+fn chunk_details(old: &Chunk, new: &Chunk) -> Vec<String> {
+    if let (Some(old_fields), Some(new_fields)) = (fields(old), fields(new)) {
+        let changed: Vec<String> = old_fields.iter().zip(&new_fields)
+            .filter(|(o, n)| o.1 != n.1)
+            .map(|(o, n)| format!("{}: {} -> {}", o.0, o.1, n.1))
+            .collect();
+        // Fields can be equal while the bytes differ, e.g. recompressed text
+        if !changed.is_empty() {
+            return changed;
+        }
+    }
+
+    let (a, b) = (old.data(), new.data());
+    let mut details = Vec::new();
+    if a.len() != b.len() {
+        details.push(format!("length: {} -> {}", a.len(), b.len()));
+    }
+    if a.len() <= SMALL_PAYLOAD && b.len() <= SMALL_PAYLOAD {
+        for k in 0..a.len().max(b.len()) {
+            let show = |v: Option<&u8>| v.map_or("--".to_string(), |v| format!("{v:02X}"));
+            if a.get(k) != b.get(k) {
+                details.push(format!("byte {k}: {} -> {}", show(a.get(k)), show(b.get(k))));
+            }
+        }
+    }
+    else {
+        let differing = a.iter().zip(b).filter(|(x, y)| x != y).count();
+        details.push(format!("{differing} of the first {} bytes differ", a.len().min(b.len())));
+    }
+    details
+}
+
+/// Decode the fields of a well-known chunk as name and value pairs.
+fn fields(chunk: &Chunk) -> Option<Vec<(&'static str, String)>> {
+    let ct = chunk.chunk_type().to_string();
+    let fields = match ct.as_str() {
+        "IHDR" => {
+            let h = ImageHeader::decode(chunk).ok()?;
+            vec![("width", h.width.to_string()), ("height", h.height.to_string()),
+                 ("bit depth", h.bit_depth.to_string()), ("color type", h.color_type.to_string()),
+                 ("compression method", h.compression_method.to_string()),
+                 ("filter method", h.filter_method.to_string()),
+                 ("interlace method", h.interlace_method.to_string())]
+        }
+        "pHYs" => {
+            let p = PhysicalDimensions::decode(chunk).ok()?;
+            vec![("x", p.x.to_string()), ("y", p.y.to_string()), ("unit", p.unit.to_string())]
+        }
+        "gAMA" => vec![("gamma", Gamma::decode(chunk).ok()?.gamma.to_string())],
+        "sRGB" => vec![("rendering intent", StandardRgb::decode(chunk).ok()?.rendering_intent.to_string())],
+        "tIME" => {
+            let t = LastModified::decode(chunk).ok()?;
+            vec![("time", format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", t.year, t.month, t.day, t.hour, t.minute, t.second))]
+        }
+        "acTL" => {
+            let a = AnimationControl::decode(chunk).ok()?;
+            vec![("frames", a.num_frames.to_string()), ("plays", a.num_plays.to_string())]
+        }
+        "fcTL" => {
+            let f = FrameControl::decode(chunk).ok()?;
+            vec![("sequence number", f.sequence_number.to_string()), ("width", f.width.to_string()),
+                 ("height", f.height.to_string()), ("x offset", f.x_offset.to_string()),
+                 ("y offset", f.y_offset.to_string()), ("delay", format!("{}/{}", f.delay_num, f.delay_den)),
+                 ("dispose", format!("{:?}", f.dispose_op)), ("blend", format!("{:?}", f.blend_op))]
+        }
+        "tEXt" | "zTXt" | "iTXt" => {
+            let data = chunk.data();
+            let keyword = &data[..data.iter().position(|&b| b == 0)?];
+            let text = String::from_utf8_lossy(&text_content(chunk)?).to_string();
+            let text = match text.char_indices().nth(MAX_TEXT_SHOWN) {
+                Some((end, _)) => format!("{:?}...", &text[..end]),
+                None => format!("{text:?}"),
+            };
+            vec![("keyword", format!("{:?}", String::from_utf8_lossy(keyword))), ("text", text)]
+        }
+        _ => return None,
+    };
+    Some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::chunk::ChunkType;
+
+    fn chunk(t: &str, data: &[u8]) -> Chunk {
+        Chunk::new(&ChunkType::from_str(t).unwrap(), data.to_vec())
+    }
+
+    fn header(width: u32) -> Chunk {
+        ImageHeader {
+            width,
+            height: 1,
+            bit_depth: 8,
+            color_type: 0,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }.encode()
+    }
+
+    fn summary(report: &DiffReport) -> Vec<(ChangeKind, &str, Option<usize>, Option<usize>)> {
+        report.changes.iter().map(|c| (c.kind, c.chunk_type.as_str(), c.old_index, c.new_index)).collect()
+    }
+
+    #[test]
+    fn test_identical() {
+        let png = Png::from_chunks(vec![header(1), chunk("IDAT", &[1]), chunk("IEND", &[])]);
+        let report = diff(&png, &png);
+        assert!(report.is_identical());
+        assert_eq!(report.to_string(), "No chunk differences.");
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = Png::from_chunks(vec![
+            header(1), chunk("gAMA", &[0, 0, 177, 143]), chunk("tEXt", b"Title\0Old"),
+            chunk("IDAT", &[1, 2, 3]), chunk("ruSt", b"gone"), chunk("IEND", &[]),
+        ]);
+        let mut new = Png::from_chunks(vec![
+            header(2), chunk("tEXt", b"Title\0New"), chunk("IDAT", &[1, 2, 4]),
+            chunk("gAMA", &[0, 0, 177, 143]), chunk("zzZz", b"new"), chunk("IEND", &[]),
+        ]);
+        new.set_trailing_data(b"extra".to_vec());
+
+        let report = diff(&old, &new);
+        assert_eq!(summary(&report), vec![
+            (ChangeKind::Modified, "IHDR", Some(0), Some(0)),
+            (ChangeKind::Moved, "gAMA", Some(1), Some(3)),
+            (ChangeKind::Modified, "tEXt", Some(2), Some(1)),
+            (ChangeKind::Modified, "IDAT", Some(3), Some(2)),
+            (ChangeKind::Removed, "ruSt", Some(4), None),
+            (ChangeKind::Added, "zzZz", None, Some(4)),
+        ]);
+        assert_eq!(report.changes[0].details, vec!["width: 1 -> 2"]);
+        assert_eq!(report.changes[2].details, vec!["text: \"Old\" -> \"New\""]);
+        assert_eq!(report.changes[3].details, vec!["byte 2: 03 -> 04"]);
+        assert_eq!(report.trailing.as_deref(), Some("0 bytes -> 5 bytes"));
+    }
+
+    #[test]
+    fn test_many_chunks() {
+        let frames = |changed: u8| {
+            let mut chunks = vec![header(1), chunk("tEXt", b"Title\0Frames")];
+            chunks.extend((0..5000u32).map(|k| chunk("IDAT", &[(k % 7) as u8 ^ changed, (k >> 8) as u8, k as u8])));
+            chunks.push(chunk("IEND", &[]));
+            Png::from_chunks(chunks)
+        };
+        let (old, mut new) = (frames(0), frames(1));
+        new.remove_chunk(&ChunkType::from_str("tEXt").unwrap()).unwrap();
+
+        let report = diff(&old, &new);
+        assert_eq!(report.changes.len(), 5001);
+        assert_eq!(summary(&report)[0], (ChangeKind::Removed, "tEXt", Some(1), None));
+        assert_eq!(summary(&report)[1], (ChangeKind::Modified, "IDAT", Some(2), Some(1)));
+        assert_eq!(summary(&report)[5000], (ChangeKind::Modified, "IDAT", Some(5001), Some(5000)));
+        assert!(diff(&old, &frames(0)).is_identical());
+    }
+}
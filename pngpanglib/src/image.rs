@@ -0,0 +1,559 @@
+//! Decoding and encoding of the image data held in IDAT chunks.
+//!
+//! The IDAT chunks of a PNG together form one zlib stream of filtered scanlines, optionally
+//! interlaced with Adam7. [ImageData] holds the result of undoing all of that: the raw,
+//! non-interlaced scanlines with samples packed as described by the image header.
+//! See sections 2 and 6 of <http://www.libpng.org/pub/png/spec/1.2/>.
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::png::{ImageHeader, Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Starting column, starting row, column step and row step of each Adam7 pass.
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2),
+];
+
+/// Raw, unfiltered and non-interlaced image data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    header: ImageHeader,
+    data: Vec<u8>,
+}
+
+/// Scanline filter selection used when encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterStrategy {
+    /// Filter type 0 on every row.
+    None,
+    /// Filter type 1 on every row.
+    Sub,
+    /// Filter type 2 on every row.
+    Up,
+    /// Filter type 3 on every row.
+    Average,
+    /// Filter type 4 on every row.
+    Paeth,
+    /// Pick the filter with the smallest sum of absolute differences for each row.
+    #[default]
+    Adaptive,
+}
+
+impl FromStr for FilterStrategy {
+    type Err = PngError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(FilterStrategy::None),
+            "sub" => Ok(FilterStrategy::Sub),
+            "up" => Ok(FilterStrategy::Up),
+            "average" => Ok(FilterStrategy::Average),
+            "paeth" => Ok(FilterStrategy::Paeth),
+            "adaptive" => Ok(FilterStrategy::Adaptive),
+            _ => Err(PngError::Unsupported(format!("filter strategy '{s}'"))),
+        }
+    }
+}
+
+/// Settings used when encoding image data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Scanline filter selection.
+    pub filter: FilterStrategy,
+    /// Deflate compression level, 0 to 9.
+    pub compression: u32,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            filter: FilterStrategy::default(),
+            compression: 6,
+        }
+    }
+}
+
+impl ImageData {
+    /// Combine a header with raw scanlines.
+    /// The interlace method of the header is reset to none, since the data is not interlaced.
+    pub fn new(mut header: ImageHeader, data: Vec<u8>) -> Result<ImageData> {
+        header.interlace_method = 0;
+        let expected = image_size(header.row_bytes(header.width)?, header.height as usize)?;
+        if data.len() != expected {
+            return Err(PngError::BadImageData(
+                format!("expected {expected} bytes of image data, found {}", data.len())));
+        }
+        Ok(ImageData { header, data })
+    }
+
+    /// Get the header describing the data.
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    /// Get the raw scanlines.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Get the raw scanlines for modification.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Get the number of bytes in each scanline.
+    pub fn row_bytes(&self) -> usize {
+        self.data.len() / self.header.height.max(1) as usize
+    }
+
+    /// Convert every pixel to 16-bit RGBA, in row-major order.
+    /// Indexed images need the PLTE data; the tRNS data, if any, supplies palette alpha or the
+    /// transparent color. Samples of lower bit depths are scaled up to the full 16-bit range.
+    pub fn to_rgba16(&self, palette: Option<&[u8]>, transparency: Option<&[u8]>) -> Result<Vec<[u16; 4]>> {
+        let header = &self.header;
+        let depth = header.bit_depth as usize;
+        let bits = header.bits_per_pixel()?;
+        let (width, row_bytes) = (header.width as usize, self.row_bytes());
+        let scale = |v: u64| (v * 65535 / ((1u64 << depth) - 1)) as u16;
+        let sample = |pixel: u64, i: usize, n: usize| (pixel >> (depth * (n - 1 - i))) & ((1u64 << depth) - 1);
+        let key = |i: usize| transparency.and_then(|t| t.get(2 * i..2 * i + 2))
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as u64);
+
+        let mut out = Vec::<[u16; 4]>::with_capacity(width * header.height as usize);
+        for row in self.data.chunks(row_bytes.max(1)).take(header.height as usize) {
+            for x in 0..width {
+                let pixel = get_pixel(row, x, bits);
+                out.push(match header.color_type {
+                    0 => {
+                        let alpha = if key(0) == Some(pixel) { 0 } else { 65535 };
+                        let v = scale(pixel);
+                        [v, v, v, alpha]
+                    }
+                    2 => {
+                        let rgb = [sample(pixel, 0, 3), sample(pixel, 1, 3), sample(pixel, 2, 3)];
+                        let opaque = transparency.is_none() || (0..3).any(|i| key(i) != Some(rgb[i]));
+                        [scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), if opaque { 65535 } else { 0 }]
+                    }
+                    3 => {
+                        let index = pixel as usize;
+                        let entry = palette.and_then(|p| p.get(3 * index..3 * index + 3)).ok_or_else(||
+                            PngError::BadImageData(format!("palette index {index} out of range")))?;
+                        let alpha = transparency.and_then(|t| t.get(index)).copied().unwrap_or(255);
+                        [entry[0] as u16 * 257, entry[1] as u16 * 257, entry[2] as u16 * 257, alpha as u16 * 257]
+                    }
+                    4 => {
+                        let v = scale(sample(pixel, 0, 2));
+                        [v, v, v, scale(sample(pixel, 1, 2))]
+                    }
+                    _ => [0, 1, 2, 3].map(|i| scale(sample(pixel, i, 4))),
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    /// Decode a zlib stream of filtered, possibly interlaced scanlines.
+    pub fn decode(header: &ImageHeader, stream: &[u8]) -> Result<ImageData> {
+        let needed = filtered_len(header)?;
+        let filtered = inflate(stream, needed)?;
+        if filtered.len() < needed {
+            return Err(PngError::BadImageData(
+                format!("expected {needed} bytes of filtered data, found {}", filtered.len())));
+        }
+        let bits = header.bits_per_pixel()?;
+        let unit = bits.div_ceil(8);
+        let row_bytes = header.row_bytes(header.width)?;
+        let (width, height) = (header.width as usize, header.height as usize);
+
+        let data = match header.interlace_method {
+            0 => unfilter(&filtered, row_bytes, height, unit)?.0,
+            1 => {
+                let mut data = vec![0u8; row_bytes * height];
+                let mut offset = 0;
+                for (x0, y0, dx, dy) in ADAM7_PASSES {
+                    let pass_w = (width + dx - 1 - x0) / dx;
+                    let pass_h = (height + dy - 1 - y0) / dy;
+                    if width <= x0 || height <= y0 || pass_w == 0 || pass_h == 0 {
+                        continue;
+                    }
+                    let pass_row_bytes = header.row_bytes(pass_w as u32)?;
+                    let (pass, used) = unfilter(&filtered[offset.min(filtered.len())..], pass_row_bytes, pass_h, unit)?;
+                    offset += used;
+                    for py in 0..pass_h {
+                        for px in 0..pass_w {
+                            let v = get_pixel(&pass[py * pass_row_bytes..], px, bits);
+                            let y = y0 + py * dy;
+                            set_pixel(&mut data[y * row_bytes..], x0 + px * dx, bits, v);
+                        }
+                    }
+                }
+                data
+            }
+            _ => return Err(PngError::MalformedChunk(ImageHeader::CHUNK_TYPE.to_string())),
+        };
+
+        ImageData::new(header.clone(), data)
+    }
+
+    /// Filter and compress the scanlines into a zlib stream.
+    pub fn encode(&self, options: &EncodeOptions) -> Result<Vec<u8>> {
+        let unit = self.header.bits_per_pixel()?.div_ceil(8);
+        let row_bytes = self.row_bytes();
+        let mut filtered = Vec::<u8>::with_capacity(self.data.len() + self.header.height as usize);
+        let zero_row = vec![0u8; row_bytes];
+
+        for (y, row) in self.data.chunks(row_bytes.max(1)).enumerate() {
+            let prev = if y == 0 { &zero_row[..] } else { &self.data[(y - 1) * row_bytes..y * row_bytes] };
+            let types: &[u8] = match options.filter {
+                FilterStrategy::None => &[0],
+                FilterStrategy::Sub => &[1],
+                FilterStrategy::Up => &[2],
+                FilterStrategy::Average => &[3],
+                FilterStrategy::Paeth => &[4],
+                FilterStrategy::Adaptive => &[0, 1, 2, 3, 4],
+            };
+            let best = types.iter()
+                .map(|&t| filter_row(t, row, prev, unit))
+                .min_by_key(|f| f[1..].iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>())
+                .unwrap();
+            filtered.extend_from_slice(&best);
+        }
+
+        Ok(deflate(&filtered, options.compression))
+    }
+}
+
+impl Png {
+    /// Get the concatenated contents of all IDAT chunks.
+    pub fn image_stream(&self) -> Vec<u8> {
+        self.chunks().iter()
+            .filter(|c| c.chunk_type() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    }
+
+    /// Decode the image data.
+    pub fn decode_image(&self) -> Result<ImageData> {
+        ImageData::decode(&self.image_header()?, &self.image_stream())
+    }
+
+    /// Decode the image data and convert every pixel to 16-bit RGBA, using PLTE and tRNS as needed.
+    pub fn decode_rgba16(&self) -> Result<Vec<[u16; 4]>> {
+        let chunk_data = |t: &str| self.chunks().iter().find(|c| c.chunk_type() == t).map(|c| c.data());
+        self.decode_image()?.to_rgba16(chunk_data("PLTE"), chunk_data("tRNS"))
+    }
+
+    /// Replace the header and image data with newly encoded image data.
+    /// The result is written as a single, non-interlaced IDAT chunk. Since critical chunks change,
+    /// the copy policy is applied and any dropped chunks are returned.
+    pub fn set_image(&mut self, image: &ImageData, options: &EncodeOptions) -> Result<Vec<Chunk>> {
+        let stream = image.encode(options)?;
+        let idat = Chunk::new(&ChunkType::from_str("IDAT").unwrap(), stream);
+
+        let mut dropped = self.replace_chunks(&ChunkType::from_str(ImageHeader::CHUNK_TYPE).unwrap(),
+                                              vec![image.header().to_chunk()]);
+        dropped.append(&mut self.replace_chunks(&ChunkType::from_str("IDAT").unwrap(), vec![idat]));
+        Ok(dropped)
+    }
+}
+
+/// Decompress a zlib stream, stopping one byte past `limit` so that a longer stream is detected
+/// without decompressing all of it.
+pub(crate) fn inflate(stream: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::<u8>::new();
+    ZlibDecoder::new(stream).take((limit as u64).saturating_add(1)).read_to_end(&mut out)
+        .map_err(|e| PngError::BadImageData(e.to_string()))?;
+    Ok(out)
+}
+
+/// Find the length of the zlib stream at the start of the data, including its Adler-32 trailer.
+/// Any bytes beyond this length are ignored by decoders.
+pub(crate) fn zlib_stream_len(stream: &[u8]) -> Result<usize> {
+    let mut decompress = Decompress::new(true);
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        let (before_in, before_out) = (decompress.total_in(), decompress.total_out());
+        let status = decompress.decompress(&stream[before_in as usize..], &mut buf, FlushDecompress::None)
+            .map_err(|e| PngError::BadImageData(e.to_string()))?;
+        if status == Status::StreamEnd {
+            return Ok(decompress.total_in() as usize);
+        }
+        if decompress.total_in() == before_in && decompress.total_out() == before_out {
+            return Err(PngError::BadImageData("truncated zlib stream".to_string()));
+        }
+    }
+}
+
+/// Get the number of filtered bytes the image data of a header decompresses to.
+/// Decoders ignore any decompressed data beyond this length.
+pub(crate) fn filtered_len(header: &ImageHeader) -> Result<usize> {
+    let (width, height) = (header.width as usize, header.height as usize);
+    match header.interlace_method {
+        0 => image_size(header.row_bytes(header.width)? + 1, height),
+        1 => {
+            let mut len = 0;
+            for (x0, y0, dx, dy) in ADAM7_PASSES {
+                let pass_w = (width + dx - 1 - x0) / dx;
+                let pass_h = (height + dy - 1 - y0) / dy;
+                if width <= x0 || height <= y0 || pass_w == 0 || pass_h == 0 {
+                    continue;
+                }
+                len = image_size(header.row_bytes(pass_w as u32)? + 1, pass_h)?.checked_add(len)
+                    .ok_or_else(|| PngError::BadImageData("interlaced image is too large".to_string()))?;
+            }
+            Ok(len)
+        }
+        _ => Err(PngError::MalformedChunk(ImageHeader::CHUNK_TYPE.to_string())),
+    }
+}
+
+/// Get the number of bytes in `rows` rows of `row_bytes` bytes, failing if it does not fit in memory.
+fn image_size(row_bytes: usize, rows: usize) -> Result<usize> {
+    row_bytes.checked_mul(rows).ok_or_else(|| PngError::BadImageData(
+        format!("{rows} rows of {row_bytes} bytes are too large")))
+}
+
+/// Compress data into a zlib stream.
+pub(crate) fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::<u8>::new(), Compression::new(level.min(9)));
+    encoder.write_all(data).expect("writing to a vector cannot fail");
+    encoder.finish().expect("writing to a vector cannot fail")
+}
+
+/// Undo scanline filtering. Returns the raw rows and the number of filtered bytes consumed.
+fn unfilter(filtered: &[u8], row_bytes: usize, height: usize, unit: usize) -> Result<(Vec<u8>, usize)> {
+    let needed = image_size(row_bytes + 1, height)?;
+    if filtered.len() < needed {
+        return Err(PngError::BadImageData(
+            format!("expected {needed} bytes of filtered data, found {}", filtered.len())));
+    }
+
+    let mut out = vec![0u8; row_bytes * height];
+    for y in 0..height {
+        let line = &filtered[y * (row_bytes + 1)..(y + 1) * (row_bytes + 1)];
+        let (done, rest) = out.split_at_mut(y * row_bytes);
+        let prev = if y == 0 { None } else { Some(&done[(y - 1) * row_bytes..]) };
+        let cur = &mut rest[..row_bytes];
+
+        for i in 0..row_bytes {
+            let a = if i >= unit { cur[i - unit] } else { 0 };
+            let b = prev.map_or(0, |p| p[i]);
+            let c = if i >= unit { prev.map_or(0, |p| p[i - unit]) } else { 0 };
+            let x = line[i + 1];
+            cur[i] = match line[0] {
+                0 => x,
+                1 => x.wrapping_add(a),
+                2 => x.wrapping_add(b),
+                3 => x.wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => x.wrapping_add(paeth(a, b, c)),
+                t => return Err(PngError::BadImageData(format!("unknown filter type {t}"))),
+            };
+        }
+    }
+
+    Ok((out, needed))
+}
+
+/// Apply a filter type to a row, returning the filter byte followed by the filtered row.
+fn filter_row(filter_type: u8, row: &[u8], prev: &[u8], unit: usize) -> Vec<u8> {
+    let mut out = Vec::<u8>::with_capacity(row.len() + 1);
+    out.push(filter_type);
+    for i in 0..row.len() {
+        let a = if i >= unit { row[i - unit] } else { 0 };
+        let b = prev[i];
+        let c = if i >= unit { prev[i - unit] } else { 0 };
+        out.push(match filter_type {
+            1 => row[i].wrapping_sub(a),
+            2 => row[i].wrapping_sub(b),
+            3 => row[i].wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+            4 => row[i].wrapping_sub(paeth(a, b, c)),
+            _ => row[i],
+        });
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    }
+    else if pb <= pc {
+        b
+    }
+    else {
+        c
+    }
+}
+
+/// Read the bits of a pixel from a row, as a big-endian integer.
+pub(crate) fn get_pixel(row: &[u8], x: usize, bits: usize) -> u64 {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        row[x * bytes..(x + 1) * bytes].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+    }
+    else {
+        let offset = x * bits;
+        let shift = 8 - bits - offset % 8;
+        ((row[offset / 8] >> shift) & ((1u8 << bits) - 1)) as u64
+    }
+}
+
+/// Write the bits of a pixel into a row.
+pub(crate) fn set_pixel(row: &mut [u8], x: usize, bits: usize, value: u64) {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        for (i, b) in row[x * bytes..(x + 1) * bytes].iter_mut().enumerate() {
+            *b = (value >> (8 * (bytes - 1 - i))) as u8;
+        }
+    }
+    else {
+        let offset = x * bits;
+        let shift = 8 - bits - offset % 8;
+        let mask = ((1u8 << bits) - 1) << shift;
+        row[offset / 8] = (row[offset / 8] & !mask) | (((value as u8) << shift) & mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_header(bit_depth: u8, color_type: u8, interlace_method: u8) -> ImageHeader {
+        ImageHeader {
+            width: 13,
+            height: 9,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method,
+        }
+    }
+
+    fn testing_image(bit_depth: u8, color_type: u8) -> ImageData {
+        let header = testing_header(bit_depth, color_type, 0);
+        let len = header.row_bytes(header.width).unwrap() * header.height as usize;
+        let data = (0..len).map(|i| (i * 37 % 251) as u8).collect();
+        ImageData::new(header, data).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_all_filters() {
+        for (bit_depth, color_type) in [(8, 6), (16, 2), (1, 0), (4, 3)] {
+            let image = testing_image(bit_depth, color_type);
+            for filter in [FilterStrategy::None, FilterStrategy::Sub, FilterStrategy::Up,
+                           FilterStrategy::Average, FilterStrategy::Paeth, FilterStrategy::Adaptive] {
+                let stream = image.encode(&EncodeOptions { filter, compression: 6 }).unwrap();
+                let decoded = ImageData::decode(image.header(), &stream).unwrap();
+                assert_eq!(decoded, image);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        for (bit_depth, color_type) in [(8, 2), (2, 0)] {
+            let image = testing_image(bit_depth, color_type);
+            let header = testing_header(bit_depth, color_type, 1);
+            let bits = header.bits_per_pixel().unwrap();
+            let row_bytes = image.row_bytes();
+
+            let mut filtered = Vec::<u8>::new();
+            for (x0, y0, dx, dy) in ADAM7_PASSES {
+                let pass_w = (13 + dx - 1 - x0) / dx;
+                let pass_row_bytes = header.row_bytes(pass_w as u32).unwrap();
+                for y in (y0..9).step_by(dy) {
+                    let mut row = vec![0u8; pass_row_bytes];
+                    for (px, x) in (x0..13).step_by(dx).enumerate() {
+                        set_pixel(&mut row, px, bits, get_pixel(&image.data()[y * row_bytes..], x, bits));
+                    }
+                    filtered.push(0);
+                    filtered.extend_from_slice(&row);
+                }
+            }
+
+            // Padding bits at the end of each row are not carried through interlacing
+            let mut expected = vec![0u8; image.data().len()];
+            for y in 0..9 {
+                for x in 0..13 {
+                    let v = get_pixel(&image.data()[y * row_bytes..], x, bits);
+                    set_pixel(&mut expected[y * row_bytes..], x, bits, v);
+                }
+            }
+
+            let decoded = ImageData::decode(&header, &deflate(&filtered, 6)).unwrap();
+            assert_eq!(decoded.data(), &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_to_rgba16() {
+        let image = |bit_depth, color_type, width, data: &[u8]| {
+            let header = ImageHeader { width, height: 1, ..testing_header(bit_depth, color_type, 0) };
+            ImageData::new(header, data.to_vec()).unwrap()
+        };
+
+        let gray = image(2, 0, 4, &[0b00_01_10_11]).to_rgba16(None, Some(&[0, 2])).unwrap();
+        assert_eq!(gray, vec![[0, 0, 0, 65535], [21845, 21845, 21845, 65535], [43690, 43690, 43690, 0], [65535; 4]]);
+
+        let indexed = image(8, 3, 2, &[1, 0]);
+        let palette = [10, 20, 30, 40, 50, 60];
+        assert_eq!(indexed.to_rgba16(Some(&palette), Some(&[128])).unwrap(),
+                   vec![[40 * 257, 50 * 257, 60 * 257, 65535], [10 * 257, 20 * 257, 30 * 257, 128 * 257]]);
+        assert!(indexed.to_rgba16(Some(&palette[..3]), None).is_err());
+
+        let rgb = image(16, 2, 1, &[0, 1, 2, 3, 4, 5]).to_rgba16(None, Some(&[0, 1, 2, 3, 4, 5])).unwrap();
+        assert_eq!(rgb, vec![[1, 0x0203, 0x0405, 0]]);
+        let gray_alpha = image(8, 4, 1, &[255, 0]).to_rgba16(None, None).unwrap();
+        assert_eq!(gray_alpha, vec![[65535, 65535, 65535, 0]]);
+    }
+
+    #[test]
+    fn test_zlib_stream_len() {
+        let mut stream = deflate(&[7u8; 1000], 6);
+        let len = stream.len();
+        stream.extend_from_slice(b"trailing");
+        assert_eq!(zlib_stream_len(&stream).unwrap(), len);
+        assert!(zlib_stream_len(&stream[..len - 2]).is_err());
+    }
+
+    #[test]
+    fn test_bad_length() {
+        let header = testing_header(8, 6, 0);
+        assert!(ImageData::new(header, vec![0; 3]).is_err());
+    }
+
+    #[test]
+    fn test_huge_header() {
+        let stream = deflate(&[0; 16], 6);
+        for interlace_method in [0, 1] {
+            let header = ImageHeader { width: i32::MAX as u32, height: i32::MAX as u32,
+                                       ..testing_header(16, 6, interlace_method) };
+            assert!(matches!(ImageData::decode(&header, &stream), Err(PngError::BadImageData(_))));
+            assert!(matches!(ImageData::new(header, Vec::new()), Err(PngError::BadImageData(_))));
+
+            let header = ImageHeader { width: 1 << 20, height: 1 << 20, ..testing_header(8, 6, interlace_method) };
+            assert!(matches!(ImageData::decode(&header, &stream), Err(PngError::BadImageData(_))));
+        }
+    }
+
+    #[test]
+    fn test_inflate_limit() {
+        let stream = deflate(&vec![0; 1 << 20], 9);
+        assert_eq!(inflate(&stream, 100).unwrap().len(), 101);
+        assert_eq!(inflate(&stream, usize::MAX).unwrap().len(), 1 << 20);
+
+        let header = testing_header(8, 0, 0);
+        assert_eq!(ImageData::decode(&header, &stream).unwrap().data(), &[0; 13 * 9]);
+    }
+}
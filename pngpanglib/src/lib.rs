@@ -7,6 +7,18 @@
 //!
 
 
+pub mod apng;
 pub mod chunk;
+pub mod codec;
+pub mod compare;
+pub mod diff;
+pub mod image;
+pub mod message;
+pub mod optimize;
+pub mod payload;
 pub mod png;
-pub mod png_command;
\ No newline at end of file
+pub mod png_command;
+pub mod registry;
+pub mod scan;
+pub mod sign;
+pub mod stego;
\ No newline at end of file
@@ -0,0 +1,218 @@
+//! Hidden messages addressed by chunk type and index or label.
+//!
+//! A file may hold any number of messages of the same chunk type. Each is addressed by its index
+//! among the chunks of that type, or by an optional label stored in front of the message. A labeled
+//! message starts with a marker and version byte, followed by `label\0message`, the layout tEXt uses
+//! for its keyword. Data without the marker is never taken as labeled, so tEXt chunks and messages
+//! written without a label are read unchanged. A label is 1 to 79 printable ASCII characters.
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::payload;
+use crate::png::{Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Longest label, matching the longest tEXt keyword.
+const MAX_LABEL_LEN: usize = 79;
+/// Start of a labeled message. The first byte never occurs in UTF-8 text.
+const LABEL_MAGIC: [u8; 3] = [0xF5, b'L', b'B'];
+/// Version of the labeled message layout following the marker.
+const LABEL_VERSION: u8 = 1;
+
+/// Which message of a chunk type to address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// The first message of the type.
+    First,
+    /// The message at this index among the chunks of the type.
+    Index(usize),
+    /// The first message with this label.
+    Label(String),
+}
+
+impl Selector {
+    /// Build a selector from an optional index and label; the index wins if both are given.
+    pub fn new(index: Option<usize>, label: Option<&str>) -> Selector {
+        match (index, label) {
+            (Some(i), _) => Selector::Index(i),
+            (None, Some(l)) => Selector::Label(l.to_string()),
+            (None, None) => Selector::First,
+        }
+    }
+}
+
+/// A message held in a chunk.
+#[derive(Clone, Copy)]
+pub struct Message<'a> {
+    /// Index among the chunks of the same type.
+    pub index: usize,
+    /// The chunk holding the message.
+    pub chunk: &'a Chunk,
+}
+
+impl<'a> Message<'a> {
+    /// Get the label of the message, if it has one.
+    pub fn label(&self) -> Option<&'a str> {
+        split_label(self.chunk.data()).0
+    }
+
+    /// Get the message without its label, checking the HMAC tag first if a secret is given and
+    /// decompressing it if it was compressed.
+    pub fn content(&self, secret: Option<&[u8]>) -> Result<Vec<u8>> {
+        let data = match secret {
+            None => self.chunk.data(),
+            Some(secret) => self.chunk.authenticated_data(secret)?,
+        };
+        payload::unpack(split_label(data).1)
+    }
+}
+
+/// Check that a label is 1 to 79 printable ASCII characters.
+fn check_label(label: &str) -> Result<()> {
+    if label.is_empty() || label.len() > MAX_LABEL_LEN || !label.bytes().all(|b| (0x20..=0x7E).contains(&b)) {
+        return Err(PngError::Unsupported(format!("label {label:?}; labels are 1 to 79 printable ASCII characters")));
+    }
+    Ok(())
+}
+
+/// Put a label in front of message data.
+pub fn labeled(label: Option<&str>, data: Vec<u8>) -> Result<Vec<u8>> {
+    match label {
+        None => Ok(data),
+        Some(label) => {
+            check_label(label)?;
+            let mut out = LABEL_MAGIC.to_vec();
+            out.push(LABEL_VERSION);
+            out.extend_from_slice(label.as_bytes());
+            out.push(0);
+            out.extend(data);
+            Ok(out)
+        }
+    }
+}
+
+/// Split chunk data into its label, if any, and the rest.
+/// Data is only labeled if it starts with the marker written by [labeled].
+pub fn split_label(data: &[u8]) -> (Option<&str>, &[u8]) {
+    let Some(rest) = data.strip_prefix(&LABEL_MAGIC).and_then(|d| d.strip_prefix(&[LABEL_VERSION])) else {
+        return (None, data);
+    };
+    let search = &rest[..rest.len().min(MAX_LABEL_LEN + 1)];
+    match search.iter().position(|&b| b == 0) {
+        Some(n) => match std::str::from_utf8(&rest[..n]) {
+            Ok(label) if check_label(label).is_ok() => (Some(label), &rest[n + 1..]),
+            _ => (None, data),
+        },
+        None => (None, data),
+    }
+}
+
+/// List the messages of a chunk type in file order.
+pub fn messages<'a>(png: &'a Png, chunk_type: &ChunkType) -> Vec<Message<'a>> {
+    png.chunks().iter()
+        .filter(|c| c.chunk_type() == chunk_type)
+        .enumerate()
+        .map(|(index, chunk)| Message { index, chunk })
+        .collect()
+}
+
+/// Find the message a selector addresses.
+pub fn find<'a>(png: &'a Png, chunk_type: &ChunkType, selector: &Selector) -> Result<Message<'a>> {
+    let all = messages(png, chunk_type);
+    match selector {
+        Selector::First => all.first().copied(),
+        Selector::Index(i) => all.get(*i).copied(),
+        Selector::Label(label) => all.into_iter().find(|m| m.label() == Some(label.as_str())),
+    }.ok_or_else(|| PngError::ChunkNotFound(chunk_type.clone()))
+}
+
+/// Remove the message a selector addresses.
+pub fn remove(png: &mut Png, chunk_type: &ChunkType, selector: &Selector) -> Result<Chunk> {
+    let index = find(png, chunk_type, selector)?.index;
+    png.remove_nth_chunk(chunk_type, index)
+}
+
+/// Remove every message of a chunk type with the given label, or every unlabeled one if the label
+/// is `None`. Returns the removed chunks.
+pub fn remove_labeled(png: &mut Png, chunk_type: &ChunkType, label: Option<&str>) -> Vec<Chunk> {
+    let indices: Vec<usize> = messages(png, chunk_type).iter()
+        .filter(|m| m.label() == label)
+        .map(|m| m.index)
+        .collect();
+    indices.into_iter().rev()
+        .filter_map(|i| png.remove_nth_chunk(chunk_type, i).ok())
+        .collect::<Vec<Chunk>>()
+        .into_iter().rev()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        Png::from_chunks(vec![
+            Chunk::new(&ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            Chunk::new(&rust, b"plain".to_vec()),
+            Chunk::new(&rust, labeled(Some("first"), b"one".to_vec()).unwrap()),
+            Chunk::new(&ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            Chunk::new(&rust, labeled(Some("second"), b"two".to_vec()).unwrap()),
+            Chunk::new(&rust, labeled(Some("first"), b"three".to_vec()).unwrap()),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ])
+    }
+
+    fn contents(png: &Png) -> Vec<(Option<String>, Vec<u8>)> {
+        messages(png, &ChunkType::from_str("ruSt").unwrap()).iter()
+            .map(|m| (m.label().map(str::to_string), m.content(None).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_list() {
+        assert_eq!(contents(&testing_png()), vec![
+            (None, b"plain".to_vec()),
+            (Some("first".to_string()), b"one".to_vec()),
+            (Some("second".to_string()), b"two".to_vec()),
+            (Some("first".to_string()), b"three".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_find() {
+        let png = testing_png();
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        let content = |s: Selector| find(&png, &rust, &s).unwrap().content(None).unwrap();
+        assert_eq!(content(Selector::First), b"plain".to_vec());
+        assert_eq!(content(Selector::Index(2)), b"two".to_vec());
+        assert_eq!(content(Selector::Label("first".to_string())), b"one".to_vec());
+        assert!(matches!(find(&png, &rust, &Selector::Index(4)), Err(PngError::ChunkNotFound(_))));
+        assert!(matches!(find(&png, &rust, &Selector::Label("third".to_string())), Err(PngError::ChunkNotFound(_))));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut png = testing_png();
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        remove(&mut png, &rust, &Selector::Index(2)).unwrap();
+        assert_eq!(remove_labeled(&mut png, &rust, Some("first")).len(), 2);
+        assert_eq!(contents(&png), vec![(None, b"plain".to_vec())]);
+    }
+
+    #[test]
+    fn test_labels() {
+        assert!(labeled(Some(""), Vec::new()).is_err());
+        assert!(labeled(Some("tab\tlabel"), Vec::new()).is_err());
+        assert!(labeled(Some(&"x".repeat(80)), Vec::new()).is_err());
+        let data = labeled(Some("Author"), b"Someone".to_vec()).unwrap();
+        assert_eq!(split_label(&data), (Some("Author"), &b"Someone"[..]));
+        assert_eq!(split_label(b"no label"), (None, &b"no label"[..]));
+        // tEXt-style data and other versions are not labels
+        assert_eq!(split_label(b"Author\0Someone"), (None, &b"Author\0Someone"[..]));
+        let mut data = data;
+        data[3] = 2;
+        assert_eq!(split_label(&data).0, None);
+    }
+}
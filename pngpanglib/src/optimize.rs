@@ -0,0 +1,449 @@
+//! Lossless size optimization of the image data.
+//!
+//! The image is decoded and re-encoded with every filter strategy and several compression levels,
+//! both in its own format and in each smaller color type and bit depth that holds exactly the same
+//! pixels, such as RGB for RGBA without transparency or a palette for few colors. The smallest
+//! result replaces the IDAT chunks as a single chunk. If the existing data is smaller still, its
+//! stream is kept and only merged into one chunk. Other chunks are left alone, so chunks hiding
+//! messages survive; removing metadata is up to the caller.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::apng::ACTL;
+use crate::chunk::{Chunk, ChunkType};
+use crate::image::{set_pixel, EncodeOptions, FilterStrategy, ImageData};
+use crate::png::{CopyPolicy, ImageHeader, Png, PngError};
+use crate::stego::stream;
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Filter strategies tried for each candidate format.
+const FILTERS: [FilterStrategy; 6] = [FilterStrategy::None, FilterStrategy::Sub, FilterStrategy::Up,
+    FilterStrategy::Average, FilterStrategy::Paeth, FilterStrategy::Adaptive];
+/// Compression level used to compare formats and filters.
+const TRIAL_LEVEL: u32 = 9;
+/// Further compression levels tried with the best format and filter; level 9 is not always smallest.
+const EXTRA_LEVELS: [u32; 3] = [6, 7, 8];
+/// Ancillary chunks whose contents depend on the color type or bit depth, preventing a format change.
+const FORMAT_DEPENDENT_TYPES: [&str; 3] = ["bKGD", "sBIT", "hIST"];
+/// Ancillary chunks describing the layout of the IDAT chunks, invalid once they are rewritten.
+const LAYOUT_DEPENDENT_TYPES: [&str; 1] = ["iDOT"];
+/// Bytes a chunk adds besides its data: length, type and CRC.
+const CHUNK_OVERHEAD: usize = 12;
+
+/// Settings for [optimize].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// Try smaller color types and bit depths that hold the same pixels.
+    pub reduce: bool,
+    /// Discard data hidden in the image stream beyond the scanlines instead of failing.
+    pub discard_stream_payload: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            reduce: true,
+            discard_stream_payload: false,
+        }
+    }
+}
+
+/// What [optimize] did to the image data.
+#[derive(Debug, Clone)]
+pub struct OptimizeReport {
+    /// Header before optimizing.
+    pub old_header: ImageHeader,
+    /// Header after optimizing.
+    pub new_header: ImageHeader,
+    /// Settings of the new image stream, or None if the existing stream was kept.
+    pub encoding: Option<EncodeOptions>,
+    /// Number of IDAT chunks before optimizing.
+    pub idat_chunks: usize,
+    /// Size of the image stream before optimizing.
+    pub stream_before: usize,
+    /// Size of the image stream after optimizing.
+    pub stream_after: usize,
+    /// Further remarks, such as why the format was kept.
+    pub notes: Vec<String>,
+}
+
+impl Display for OptimizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (old, new) = (format_name(&self.old_header), format_name(&self.new_header));
+        if old == new {
+            writeln!(f, "Image format: {old}, unchanged")?;
+        }
+        else {
+            writeln!(f, "Image format: {old} -> {new}")?;
+        }
+        let plural = if self.idat_chunks == 1 { "" } else { "s" };
+        writeln!(f, "Image data: {} IDAT chunk{plural} of {} bytes -> 1 IDAT chunk of {} bytes",
+                 self.idat_chunks, self.stream_before, self.stream_after)?;
+        match self.encoding {
+            Some(e) => write!(f, "Encoding: {} filter, compression level {}",
+                              format!("{:?}", e.filter).to_lowercase(), e.compression)?,
+            None => write!(f, "Encoding: existing stream kept")?,
+        }
+        for note in &self.notes {
+            write!(f, "\nNote: {note}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Describe the color type and bit depth of a header, e.g. "RGBA 8-bit".
+fn format_name(header: &ImageHeader) -> String {
+    let color = match header.color_type {
+        0 => "grayscale",
+        2 => "RGB",
+        3 => "indexed",
+        4 => "grayscale with alpha",
+        6 => "RGBA",
+        _ => "unknown",
+    };
+    format!("{color} {}-bit", header.bit_depth)
+}
+
+/// Image data in one format, with the palette and transparency data that go with it.
+struct Candidate {
+    image: ImageData,
+    palette: Option<Vec<u8>>,
+    transparency: Option<Vec<u8>>,
+}
+
+impl Candidate {
+    /// Get the number of bytes the image chunks take with a stream of the given length.
+    fn cost(&self, stream_len: usize) -> usize {
+        let aux = |d: &Option<Vec<u8>>| d.as_ref().map_or(0, |d| d.len() + CHUNK_OVERHEAD);
+        stream_len + CHUNK_OVERHEAD + aux(&self.palette) + aux(&self.transparency)
+    }
+}
+
+/// Re-encode the image data in the smallest form found, replacing the IDAT chunks with a single one.
+/// When the format changes, IHDR, PLTE and tRNS are rewritten to match. Other chunks are kept
+/// regardless of the copy policy, since the pixels are unchanged; chunks describing the old IDAT
+/// layout, such as iDOT, are removed.
+/// Fails if the image stream holds data beyond the scanlines, unless told to discard it.
+pub fn optimize(png: &mut Png, options: &OptimizeOptions) -> Result<OptimizeReport> {
+    let header = png.image_header()?;
+    let mut notes = Vec::<String>::new();
+
+    let payload = match stream::extract(png) {
+        Ok(payload) => Some(payload.len()),
+        Err(PngError::NoPayload) => None,
+        Err(e) => return Err(e),
+    };
+    if let Some(len) = payload {
+        if !options.discard_stream_payload {
+            return Err(PngError::Unsupported(format!(
+                "the image data holds {len} bytes beyond the scanlines, which optimizing would discard")));
+        }
+        notes.push(format!("discarded {len} bytes hidden in the image data"));
+    }
+
+    let chunk_data = |t: &str| png.chunks().iter().find(|c| c.chunk_type() == t).map(|c| c.data().to_vec());
+    let original = Candidate {
+        image: png.decode_image()?,
+        palette: chunk_data("PLTE"),
+        transparency: chunk_data("tRNS"),
+    };
+    let old_stream = png.image_stream();
+    let idat_chunks = png.chunks().iter().filter(|c| c.chunk_type() == "IDAT").count();
+
+    let mut candidates = Vec::<Candidate>::new();
+    if options.reduce {
+        let blocker = FORMAT_DEPENDENT_TYPES.iter().chain(&[ACTL])
+            .find(|&&t| png.chunks().iter().any(|c| c.chunk_type() == t));
+        match blocker {
+            Some(t) => notes.push(format!("kept the image format because of the {t} chunk")),
+            None => {
+                let pixels = original.image.to_rgba16(original.palette.as_deref(), original.transparency.as_deref())?;
+                let keep_gray = png.chunks().iter().any(|c| c.chunk_type() == "iCCP")
+                    .then_some(matches!(header.color_type, 0 | 4));
+                candidates.extend(reductions(&pixels, &header, keep_gray)?);
+            }
+        }
+    }
+    candidates.insert(0, original);
+
+    // Pick the format and filter at one level, then try other levels for that pair
+    let mut best: Option<(usize, usize, EncodeOptions, Vec<u8>)> = None;
+    for (i, candidate) in candidates.iter().enumerate() {
+        for filter in FILTERS {
+            let encoding = EncodeOptions { filter, compression: TRIAL_LEVEL };
+            let stream = candidate.image.encode(&encoding)?;
+            let cost = candidate.cost(stream.len());
+            if best.as_ref().is_none_or(|b| cost < b.0) {
+                best = Some((cost, i, encoding, stream));
+            }
+        }
+    }
+    let (mut cost, index, mut encoding, mut new_stream) = best.expect("there is always a candidate");
+    let candidate = &candidates[index];
+    for compression in EXTRA_LEVELS {
+        let trial = EncodeOptions { compression, ..encoding };
+        let stream = candidate.image.encode(&trial)?;
+        if candidate.cost(stream.len()) < cost {
+            (cost, encoding, new_stream) = (candidate.cost(stream.len()), trial, stream);
+        }
+    }
+
+    let idat_type = ChunkType::from_str("IDAT").unwrap();
+    let keep_stream = payload.is_none() && candidates[0].cost(old_stream.len()) <= cost;
+    if keep_stream && idat_chunks == 1 {
+        return Ok(OptimizeReport {
+            old_header: header.clone(),
+            new_header: header,
+            encoding: None,
+            idat_chunks,
+            stream_before: old_stream.len(),
+            stream_after: old_stream.len(),
+            notes,
+        });
+    }
+
+    let policy = png.copy_policy();
+    png.set_copy_policy(CopyPolicy::KeepAll);
+    let new_header = if keep_stream {
+        png.replace_chunks(&idat_type, vec![Chunk::new(&idat_type, old_stream.clone())]);
+        header.clone()
+    }
+    else {
+        let new_header = candidate.image.header().clone();
+        png.replace_chunks(&ChunkType::from_str(ImageHeader::CHUNK_TYPE).unwrap(), vec![new_header.to_chunk()]);
+        png.replace_chunks(&idat_type, vec![Chunk::new(&idat_type, new_stream.clone())]);
+        if index > 0 {
+            set_aux_chunk(png, "tRNS", candidate.transparency.as_ref())?;
+            let keep_palette = matches!(header.color_type, 2 | 6) && matches!(new_header.color_type, 2 | 6);
+            if !keep_palette {
+                set_aux_chunk(png, "PLTE", candidate.palette.as_ref())?;
+            }
+        }
+        new_header
+    };
+    for t in LAYOUT_DEPENDENT_TYPES {
+        if !png.remove_all(&ChunkType::from_str(t).unwrap()).0.is_empty() {
+            notes.push(format!("removed the {t} chunk, which described the old IDAT layout"));
+        }
+    }
+    png.set_copy_policy(policy);
+
+    Ok(OptimizeReport {
+        old_header: header,
+        new_header,
+        encoding: (!keep_stream).then_some(encoding),
+        idat_chunks,
+        stream_before: old_stream.len(),
+        stream_after: if keep_stream { old_stream.len() } else { new_stream.len() },
+        notes,
+    })
+}
+
+/// Replace the chunks of a type with one holding the data, placed before the image data, or remove them.
+fn set_aux_chunk(png: &mut Png, chunk_type: &str, data: Option<&Vec<u8>>) -> Result<()> {
+    let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+    png.remove_all(&chunk_type);
+    match data {
+        Some(d) => png.insert_before(&ChunkType::from_str("IDAT").unwrap(), Chunk::new(&chunk_type, d.clone())).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Build every smaller format that holds the pixels exactly: for each color type, the lowest bit depth.
+/// With `keep_gray` set, only formats that are grayscale when it is true, or color when false, are built,
+/// as an ICC profile is only valid for one of the two.
+fn reductions(pixels: &[[u16; 4]], header: &ImageHeader, keep_gray: Option<bool>) -> Result<Vec<Candidate>> {
+    let opaque = pixels.iter().all(|p| p[3] == 65535);
+    let gray = pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let fits = |depth: u8| pixels.iter().all(|p| p.iter().all(|&v| v % step(depth) == 0));
+    let depth = if fits(8) { 8 } else { 16 };
+
+    let mut formats = Vec::<(u8, u8)>::new();
+    if gray && opaque {
+        formats.push((0, [1, 2, 4, 8, 16].into_iter().find(|&d| fits(d)).unwrap()));
+    }
+    if gray {
+        formats.push((4, depth));
+    }
+    if opaque {
+        formats.push((2, depth));
+    }
+    formats.push((6, depth));
+
+    let bits = header.bits_per_pixel()?;
+    let mut candidates = Vec::<Candidate>::new();
+    for (color_type, bit_depth) in formats {
+        let new_header = ImageHeader { color_type, bit_depth, interlace_method: 0, ..header.clone() };
+        if new_header.bits_per_pixel()? < bits && keep_gray.is_none_or(|g| g == matches!(color_type, 0 | 4)) {
+            candidates.push(Candidate {
+                image: pack(pixels, new_header, |p| samples(color_type).iter()
+                    .fold(0u64, |acc, &i| (acc << bit_depth) | (p[i] / step(bit_depth)) as u64))?,
+                palette: None,
+                transparency: None,
+            });
+        }
+    }
+
+    if depth == 8 && keep_gray != Some(true) {
+        if let Some(candidate) = palette_candidate(pixels, header)? {
+            if candidate.image.header().bits_per_pixel()? < bits {
+                candidates.push(candidate);
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// Build an indexed version of 8-bit pixels, if they have at most 256 colors.
+/// Translucent colors come first so the tRNS chunk can stop after them.
+fn palette_candidate(pixels: &[[u16; 4]], header: &ImageHeader) -> Result<Option<Candidate>> {
+    let mut colors = Vec::<[u16; 4]>::new();
+    let mut seen = HashSet::<[u16; 4]>::new();
+    for p in pixels {
+        if seen.insert(*p) {
+            colors.push(*p);
+            if colors.len() > 256 {
+                return Ok(None);
+            }
+        }
+    }
+    colors.sort_by_key(|c| c[3] == 65535);
+    let index: HashMap<[u16; 4], u64> = colors.iter().enumerate().map(|(i, c)| (*c, i as u64)).collect();
+
+    let bit_depth = [1u8, 2, 4, 8].into_iter().find(|&d| colors.len() <= 1 << d).unwrap();
+    let translucent = colors.iter().take_while(|c| c[3] != 65535).count();
+    let new_header = ImageHeader { color_type: 3, bit_depth, interlace_method: 0, ..header.clone() };
+    Ok(Some(Candidate {
+        image: pack(pixels, new_header, |p| index[p])?,
+        palette: Some(colors.iter().flat_map(|c| [c[0], c[1], c[2]].map(|v| (v / 257) as u8)).collect()),
+        transparency: (translucent > 0).then(|| colors[..translucent].iter().map(|c| (c[3] / 257) as u8).collect()),
+    }))
+}
+
+/// Get the 16-bit value of one step of a sample of the given bit depth, e.g. 257 for 8 bits.
+fn step(depth: u8) -> u16 {
+    (65535 / ((1u32 << depth) - 1)) as u16
+}
+
+/// Get the RGBA channels a color type stores, in order; gray is taken from red.
+fn samples(color_type: u8) -> &'static [usize] {
+    match color_type {
+        0 => &[0],
+        2 => &[0, 1, 2],
+        4 => &[0, 3],
+        _ => &[0, 1, 2, 3],
+    }
+}
+
+/// Lay out pixels as scanlines of the header's format, using `value` to get the bits of each pixel.
+fn pack<F>(pixels: &[[u16; 4]], header: ImageHeader, value: F) -> Result<ImageData>
+    where F: Fn(&[u16; 4]) -> u64 {
+    let bits = header.bits_per_pixel()?;
+    let row_bytes = header.row_bytes(header.width)?;
+    let width = header.width as usize;
+    let mut data = vec![0u8; row_bytes * header.height as usize];
+    for (i, p) in pixels.iter().enumerate() {
+        set_pixel(&mut data[(i / width) * row_bytes..], i % width, bits, value(p));
+    }
+    ImageData::new(header, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::compare;
+    use crate::stego::stream::StreamMode;
+
+    fn testing_png(color_type: u8, data: Vec<u8>, extra: &[(&str, &[u8])]) -> Png {
+        let header = ImageHeader {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let stream = ImageData::new(header.clone(), data).unwrap()
+            .encode(&EncodeOptions { filter: FilterStrategy::None, compression: 0 }).unwrap();
+        let mut chunks = vec![header.to_chunk()];
+        chunks.extend(extra.iter().map(|(t, d)| Chunk::new(&ChunkType::from_str(t).unwrap(), d.to_vec())));
+        let (first, second) = stream.split_at(stream.len() / 2);
+        chunks.push(Chunk::new(&ChunkType::from_str("IDAT").unwrap(), first.to_vec()));
+        chunks.push(Chunk::new(&ChunkType::from_str("IDAT").unwrap(), second.to_vec()));
+        chunks.push(Chunk::new(&ChunkType::END_CHUNK, Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    /// Opaque RGBA pixels in two colors.
+    fn two_colors() -> Vec<u8> {
+        (0..16).flat_map(|i| if i % 3 == 0 { [200, 10, 10, 255] } else { [0, 0, 90, 255] }).collect()
+    }
+
+    #[test]
+    fn test_reduce_to_palette() {
+        let mut png = testing_png(6, two_colors(), &[("RuSt", b"hidden")]);
+        let original = testing_png(6, two_colors(), &[]);
+        let report = optimize(&mut png, &OptimizeOptions::default()).unwrap();
+
+        assert_eq!((report.new_header.color_type, report.new_header.bit_depth), (3, 1));
+        assert_eq!(report.idat_chunks, 2);
+        assert!(report.stream_after < report.stream_before);
+        assert_eq!(types(&png), vec!["IHDR", "RuSt", "PLTE", "IDAT", "IEND"]);
+        assert!(compare(&original, &png).unwrap().is_identical());
+        assert!(report.to_string().starts_with("Image format: RGBA 8-bit -> indexed 1-bit\n"));
+    }
+
+    #[test]
+    fn test_reduce_to_gray() {
+        let pixels: Vec<u8> = (0..16).flat_map(|i| [i * 17; 3]).collect();
+        let mut png = testing_png(2, pixels.clone(), &[]);
+        let original = testing_png(2, pixels.clone(), &[]);
+        let report = optimize(&mut png, &OptimizeOptions::default()).unwrap();
+        assert_eq!((report.new_header.color_type, report.new_header.bit_depth), (0, 4));
+        assert!(compare(&original, &png).unwrap().is_identical());
+
+        // An RGB profile must stay with a color image
+        let mut png = testing_png(2, pixels, &[("iCCP", b"profile")]);
+        let report = optimize(&mut png, &OptimizeOptions::default()).unwrap();
+        assert!(matches!(report.new_header.color_type, 2 | 3));
+        assert!(compare(&original, &png).unwrap().is_identical());
+    }
+
+    #[test]
+    fn test_format_kept() {
+        let mut png = testing_png(6, two_colors(), &[("bKGD", &[0, 0, 0, 0, 0, 0])]);
+        let report = optimize(&mut png, &OptimizeOptions::default()).unwrap();
+        assert_eq!(report.new_header.color_type, 6);
+        assert_eq!(report.notes, vec!["kept the image format because of the bKGD chunk"]);
+
+        let mut png = testing_png(6, two_colors(), &[]);
+        let report = optimize(&mut png, &OptimizeOptions { reduce: false, ..Default::default() }).unwrap();
+        assert_eq!(report.new_header.color_type, 6);
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "IEND"]);
+
+        // Already optimal: nothing changes
+        let again = optimize(&mut png, &OptimizeOptions { reduce: false, ..Default::default() }).unwrap();
+        assert_eq!(again.encoding, None);
+        assert_eq!(again.stream_after, report.stream_after);
+    }
+
+    #[test]
+    fn test_stream_payload() {
+        let mut png = testing_png(6, two_colors(), &[]);
+        stream::embed(&mut png, b"secret", StreamMode::Trailer, 6).unwrap();
+        assert!(matches!(optimize(&mut png, &OptimizeOptions::default()), Err(PngError::Unsupported(_))));
+
+        let options = OptimizeOptions { discard_stream_payload: true, ..Default::default() };
+        let report = optimize(&mut png, &options).unwrap();
+        assert_eq!(report.notes, vec!["discarded 6 bytes hidden in the image data"]);
+        assert!(matches!(stream::extract(&png), Err(PngError::NoPayload)));
+    }
+}
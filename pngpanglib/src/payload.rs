@@ -0,0 +1,198 @@
+//! Optional compression of payloads before they are embedded.
+//!
+//! A compressed payload starts with a header of a three-byte marker, a version byte and a byte
+//! identifying its codec. The marker never occurs in UTF-8 text, and data without a valid header
+//! is read back unchanged, so plain and binary payloads written without compression are never
+//! mistaken for compressed ones. Deflate is always available; zstd and brotli require the cargo
+//! features of the same names.
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::png::PngError;
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Start of a compressed payload. The first byte never occurs in UTF-8 text.
+const PACKED_MAGIC: [u8; 3] = [0xF5, b'P', b'K'];
+/// Version of the header layout following the marker.
+const PACKED_VERSION: u8 = 1;
+const DEFLATE_CODEC: u8 = 1;
+const ZSTD_CODEC: u8 = 2;
+const BROTLI_CODEC: u8 = 3;
+
+/// Largest payload read back, compressed or not. Longer payloads are refused rather than allocated.
+pub const MAX_UNPACKED_LEN: usize = 64 * 1024 * 1024;
+
+/// Compression applied to a payload before embedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadCodec {
+    /// Store the payload as is.
+    #[default]
+    None,
+    /// Raw deflate.
+    Deflate,
+    /// Zstandard.
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// Brotli.
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl FromStr for PayloadCodec {
+    type Err = PngError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(PayloadCodec::None),
+            "deflate" => Ok(PayloadCodec::Deflate),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(PayloadCodec::Zstd),
+            #[cfg(feature = "brotli")]
+            "brotli" => Ok(PayloadCodec::Brotli),
+            _ => Err(PngError::Unsupported(format!("compression codec {s}"))),
+        }
+    }
+}
+
+/// Build the header of a payload compressed with a codec.
+fn header(codec: u8) -> Vec<u8> {
+    let mut header = PACKED_MAGIC.to_vec();
+    header.extend_from_slice(&[PACKED_VERSION, codec]);
+    header
+}
+
+/// Split a compressed payload into its codec and compressed data, if it has a valid header.
+fn split_header(data: &[u8]) -> Option<(u8, &[u8])> {
+    match data.strip_prefix(&PACKED_MAGIC)? {
+        [PACKED_VERSION, codec @ (DEFLATE_CODEC | ZSTD_CODEC | BROTLI_CODEC), body @ ..] => Some((*codec, body)),
+        _ => None,
+    }
+}
+
+/// Compress a payload and prefix it with its header.
+/// With [PayloadCodec::None] the payload is returned unchanged.
+pub fn pack(payload: &[u8], codec: PayloadCodec) -> Vec<u8> {
+    match codec {
+        PayloadCodec::None => payload.to_vec(),
+        PayloadCodec::Deflate => {
+            let mut encoder = DeflateEncoder::new(header(DEFLATE_CODEC), Compression::best());
+            encoder.write_all(payload).expect("writing to a vector cannot fail");
+            encoder.finish().expect("writing to a vector cannot fail")
+        }
+        #[cfg(feature = "zstd")]
+        PayloadCodec::Zstd => {
+            let mut packed = header(ZSTD_CODEC);
+            packed.append(&mut zstd::encode_all(payload, 19).expect("writing to a vector cannot fail"));
+            packed
+        }
+        #[cfg(feature = "brotli")]
+        PayloadCodec::Brotli => {
+            let mut packed = header(BROTLI_CODEC);
+            let mut reader = payload;
+            brotli::BrotliCompress(&mut reader, &mut packed, &brotli::enc::BrotliEncoderParams::default())
+                .expect("writing to a vector cannot fail");
+            packed
+        }
+    }
+}
+
+/// Check whether data starts with a valid compressed payload header.
+pub fn is_packed(data: &[u8]) -> bool {
+    split_header(data).is_some()
+}
+
+/// Decompress a payload written by [pack]. Data without a valid header is returned unchanged.
+/// Fails with [PngError::BadPayload] if the payload expands beyond [MAX_UNPACKED_LEN].
+pub fn unpack(data: &[u8]) -> Result<Vec<u8>> {
+    let Some((codec, body)) = split_header(data) else {
+        return Ok(data.to_vec());
+    };
+    let bad_payload = |e: std::io::Error| PngError::BadPayload(e.to_string());
+
+    let decoder: Box<dyn Read + '_> = match codec {
+        DEFLATE_CODEC => Box::new(DeflateDecoder::new(body)),
+        #[cfg(feature = "zstd")]
+        ZSTD_CODEC => Box::new(zstd::stream::read::Decoder::new(body).map_err(bad_payload)?),
+        #[cfg(feature = "brotli")]
+        BROTLI_CODEC => Box::new(brotli::Decompressor::new(body, 4096)),
+        #[cfg(not(feature = "zstd"))]
+        ZSTD_CODEC => return Err(PngError::Unsupported("payload is compressed with zstd".to_string())),
+        #[cfg(not(feature = "brotli"))]
+        BROTLI_CODEC => return Err(PngError::Unsupported("payload is compressed with brotli".to_string())),
+        _ => return Ok(data.to_vec()),
+    };
+    let mut out = Vec::<u8>::new();
+    decoder.take(MAX_UNPACKED_LEN as u64 + 1).read_to_end(&mut out).map_err(bad_payload)?;
+    if out.len() > MAX_UNPACKED_LEN {
+        return Err(PngError::BadPayload(format!("payload expands to more than {MAX_UNPACKED_LEN} bytes")));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"This is where your secret message will be! This is where your secret message will be!";
+
+    #[test]
+    fn test_round_trip() {
+        let codecs = [
+            PayloadCodec::None,
+            PayloadCodec::Deflate,
+            #[cfg(feature = "zstd")]
+            PayloadCodec::Zstd,
+            #[cfg(feature = "brotli")]
+            PayloadCodec::Brotli,
+        ];
+
+        for codec in codecs {
+            let packed = pack(MESSAGE, codec);
+            assert_eq!(is_packed(&packed), codec != PayloadCodec::None);
+            assert_eq!(unpack(&packed).unwrap(), MESSAGE.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_deflate_shrinks() {
+        assert!(pack(MESSAGE, PayloadCodec::Deflate).len() < MESSAGE.len());
+    }
+
+    #[test]
+    fn test_plain_text_unchanged() {
+        assert_eq!(unpack(b"RuSt").unwrap(), b"RuSt".to_vec());
+        assert!(!is_packed(b"RuSt"));
+    }
+
+    #[test]
+    fn test_corrupt_payload() {
+        let mut data = header(DEFLATE_CODEC);
+        data.extend_from_slice(&[0xFF, 0xFF]);
+        assert!(matches!(unpack(&data), Err(PngError::BadPayload(_))));
+    }
+
+    #[test]
+    fn test_expansion_limit() {
+        let packed = |len: usize| {
+            let mut encoder = DeflateEncoder::new(header(DEFLATE_CODEC), Compression::fast());
+            encoder.write_all(&vec![0; len]).unwrap();
+            encoder.finish().unwrap()
+        };
+        assert!(matches!(unpack(&packed(MAX_UNPACKED_LEN + 1)), Err(PngError::BadPayload(_))));
+        assert_eq!(unpack(&packed(MAX_UNPACKED_LEN)).unwrap().len(), MAX_UNPACKED_LEN);
+    }
+
+    #[test]
+    fn test_raw_bytes_unchanged() {
+        for data in [&[0xF5, 0xFF, 0xFF][..], &[0xF6, 1, 2], &[0xF5, b'P', b'K', 2, 1, 0], &[0xF5, b'P', b'K', 1, 9], &[]] {
+            assert_eq!(unpack(data).unwrap(), data.to_vec());
+            assert!(!is_packed(data));
+        }
+    }
+}
@@ -1,47 +1,107 @@
 //! An implementation of the PNG file format.
 //!
 //! Contains structure and methods to handle the parts of a PNG file touched by the PNGme specification.
+//! The same structure also holds MNG and JNG datastreams, which share the PNG chunk layout.
 //!
 
 pub(crate) mod png_error;
+mod image_header;
+mod container;
 
 use std::fmt::{Display, Formatter};
 use std::path::Path;
 
-use crate::chunk::Chunk;
+use crate::chunk::{Chunk, DataMut};
+use crate::chunk::ChunkError;
 use crate::chunk::ChunkType;
 pub use crate::png::png_error::PngError;
+pub use crate::png::image_header::ImageHeader;
+pub use crate::png::container::Container;
 
 /// The fundamental structure of a PNG file is a header followed by chunks.
 pub struct Png {
-    header: [u8; 8],
+    container: Container,
     my_chunks: Vec<Chunk>,
+    copy_policy: CopyPolicy,
+    trailing: Vec<u8>,
+}
+
+/// Determines what happens to ancillary chunks that are not safe to copy when critical chunks change.
+/// See section 4.4 of the PNG specification at <http://www.libpng.org/pub/png/spec/1.2/>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyPolicy {
+    /// Drop unsafe-to-copy chunks that are not standard PNG chunks, as the specification requires.
+    #[default]
+    DropUnsafe,
+    /// Keep every chunk, for callers who know the chunks remain valid.
+    KeepAll,
 }
 
 type Result<T> = std::result::Result<T, PngError>;
 
 impl Png {
     /// The header found at the start of each PNG file.
-    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_HEADER: [u8; 8] = Container::PNG_SIGNATURE;
 
     /// Combine a vector of chunks with the header.
     /// Does not validate chunks
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
+        Png::from_container_chunks(Container::Png, chunks)
+    }
+
+    /// Combine a vector of chunks with the header of the given container format.
+    /// Does not validate chunks
+    pub fn from_container_chunks(container: Container, chunks: Vec<Chunk>) -> Png {
         Png {
-            header: Png::STANDARD_HEADER,
-            my_chunks: chunks
+            container,
+            my_chunks: chunks,
+            copy_policy: CopyPolicy::default(),
+            trailing: Vec::new(),
         }
     }
 
+    /// Get the container format: PNG, MNG or JNG.
+    pub fn container(&self) -> Container {
+        self.container
+    }
+
+    /// Get the bytes that followed the end chunk in the source file.
+    /// Decoders ignore these bytes, which makes them a common hiding place.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    /// Set the bytes written after the end chunk.
+    pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+        self.trailing = data;
+    }
+
+    /// Remove and return the bytes following the end chunk, so they are not saved.
+    pub fn take_trailing_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailing)
+    }
+
+    /// Get the policy applied to unsafe-to-copy chunks when critical chunks change.
+    pub fn copy_policy(&self) -> CopyPolicy {
+        self.copy_policy
+    }
+
+    /// Set the policy applied to unsafe-to-copy chunks when critical chunks change.
+    pub fn set_copy_policy(&mut self, policy: CopyPolicy) {
+        self.copy_policy = policy;
+    }
+
     /// Append a chunk.
     /// Inserts a chunk before the end chunk, unless the chunk type is an end type. In this case,
     /// the chunk is placed at the end or ignored if already present.
+    /// The end chunk is IEND for PNG and JNG, and MEND for MNG.
     pub fn append_chunk(&mut self, chunk: Chunk) {
+        let end = self.container.end_chunk_type();
         let mut idx = self.my_chunks.len();
         if let Some(last) = self.my_chunks.last() {
-            if last.chunk_type() == &ChunkType::END_CHUNK {
+            if last.chunk_type() == end {
                 idx -= 1;
-                if chunk.chunk_type() == &ChunkType::END_CHUNK {
+                if chunk.chunk_type() == end {
                     return;
                 }
             }
@@ -49,23 +109,228 @@ impl Png {
         self.my_chunks.insert(idx, chunk);
     }
 
+    /// Insert a chunk at the given position, without any checks.
+    pub(crate) fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.my_chunks.insert(index, chunk);
+    }
+
+    /// Get the position of the end chunk, if the last chunk is one.
+    fn end_index(&self) -> Option<usize> {
+        self.my_chunks.last()
+            .filter(|c| c.chunk_type() == self.container.end_chunk_type())
+            .map(|_| self.my_chunks.len() - 1)
+    }
+
+    /// Check that a chunk may be placed at a position without displacing the end chunk.
+    fn check_position(&self, index: usize, chunk: &Chunk) -> Result<()> {
+        let end = self.container.end_chunk_type();
+        if chunk.chunk_type() == end {
+            return Err(PngError::InvalidEdit(format!("{end} can only be added with append_chunk")));
+        }
+        let limit = self.end_index().unwrap_or(self.my_chunks.len());
+        if index > limit {
+            return Err(PngError::InvalidEdit(format!("position {index} is after {end} at {limit}")));
+        }
+        Ok(())
+    }
+
+    /// Refuse to remove the end chunk, which every file must keep as its last chunk.
+    fn check_removable(&self, chunk_type: &ChunkType) -> Result<()> {
+        let end = self.container.end_chunk_type();
+        if chunk_type == end {
+            return Err(PngError::InvalidEdit(format!("{end} cannot be removed")));
+        }
+        Ok(())
+    }
+
+    /// Apply the copy policy after an edit, if the edit touched a critical chunk.
+    fn after_edit(&mut self, critical: bool) -> Vec<Chunk> {
+        if critical {
+            self.drop_unsafe_to_copy()
+        }
+        else {
+            Vec::new()
+        }
+    }
+
+    /// Insert a chunk at a position. The position may not be after the end chunk, and the end chunk
+    /// itself can only be added with [Png::append_chunk].
+    /// As with [Png::replace_chunks], inserting a critical chunk applies the copy policy, and any
+    /// dropped chunks are returned.
+    pub fn insert_at(&mut self, index: usize, chunk: Chunk) -> Result<Vec<Chunk>> {
+        self.check_position(index, &chunk)?;
+        let critical = chunk.chunk_type().is_critical();
+        self.my_chunks.insert(index, chunk);
+        Ok(self.after_edit(critical))
+    }
+
+    /// Insert a chunk before the first chunk of a type.
+    pub fn insert_before(&mut self, chunk_type: &ChunkType, chunk: Chunk) -> Result<Vec<Chunk>> {
+        let index = self.my_chunks.iter().position(|c| c.chunk_type() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunk_type.clone()))?;
+        self.insert_at(index, chunk)
+    }
+
+    /// Insert a chunk after the last chunk of a type.
+    pub fn insert_after(&mut self, chunk_type: &ChunkType, chunk: Chunk) -> Result<Vec<Chunk>> {
+        let index = self.my_chunks.iter().rposition(|c| c.chunk_type() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunk_type.clone()))?;
+        self.insert_at(index + 1, chunk)
+    }
+
+    /// Replace the chunk at a position, returning the old chunk and the chunks dropped by the copy
+    /// policy if either chunk is critical. The end chunk cannot be replaced.
+    pub fn replace(&mut self, index: usize, chunk: Chunk) -> Result<(Chunk, Vec<Chunk>)> {
+        if index >= self.my_chunks.len() || Some(index) == self.end_index() {
+            return Err(PngError::InvalidEdit(format!("no replaceable chunk at position {index}")));
+        }
+        self.check_position(index, &chunk)?;
+        let old = std::mem::replace(&mut self.my_chunks[index], chunk);
+        let critical = old.chunk_type().is_critical() || self.my_chunks[index].chunk_type().is_critical();
+        Ok((old, self.after_edit(critical)))
+    }
+
+    /// Move the chunk at position `from` so it ends up at position `to`. The end chunk cannot be
+    /// moved, and no chunk can be moved after it. Moving a critical chunk applies the copy policy,
+    /// and any dropped chunks are returned.
+    pub fn move_chunk(&mut self, from: usize, to: usize) -> Result<Vec<Chunk>> {
+        if from >= self.my_chunks.len() || Some(from) == self.end_index() {
+            return Err(PngError::InvalidEdit(format!("no movable chunk at position {from}")));
+        }
+        let chunk = self.my_chunks.remove(from);
+        match self.check_position(to, &chunk) {
+            Ok(()) => {
+                let critical = chunk.chunk_type().is_critical();
+                self.my_chunks.insert(to, chunk);
+                Ok(self.after_edit(critical))
+            }
+            Err(e) => {
+                self.my_chunks.insert(from, chunk);
+                Err(e)
+            }
+        }
+    }
+
+    /// Keep only the chunks for which `keep` returns true. The end chunk is always kept.
+    /// Removing a critical chunk applies the copy policy, and any chunks it drops are returned.
+    pub fn retain<F>(&mut self, mut keep: F) -> Vec<Chunk>
+        where F: FnMut(&Chunk) -> bool {
+        let end = self.container.end_chunk_type();
+        let mut critical = false;
+        self.my_chunks.retain(|c| {
+            let kept = c.chunk_type() == end || keep(c);
+            critical |= !kept && c.chunk_type().is_critical();
+            kept
+        });
+        self.after_edit(critical)
+    }
+
+    /// Iterate over the chunks in file order.
+    pub fn iter(&self) -> std::slice::Iter<'_, Chunk> {
+        self.my_chunks.iter()
+    }
+
+    /// Iterate over the chunks in file order to edit their data in place.
+    /// The chunks themselves cannot be swapped out, so their types and order stay as they are.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ChunkMut<'_>> {
+        self.my_chunks.iter_mut().map(ChunkMut)
+    }
+
+    /// Iterate over the chunks of a type in file order.
+    pub fn chunks_of_type<'a>(&'a self, chunk_type: &'a ChunkType) -> impl Iterator<Item = &'a Chunk> + 'a {
+        self.my_chunks.iter().filter(move |c| c.chunk_type() == chunk_type)
+    }
+
     /// Remove a chunk.
-    /// Removes and returns the first chunk of the specified type found.
+    /// Removes and returns the first chunk of the specified type found. The end chunk cannot be removed.
     pub fn remove_chunk(&mut self, chunk_type: &ChunkType) -> Result<Chunk> {
+        self.check_removable(chunk_type)?;
         for (i, c) in self.my_chunks.iter().enumerate() {
             if c.chunk_type() == chunk_type {
                 return Ok(self.my_chunks.remove(i));
             }
         }
-        Err(PngError::ChunkNotFound)
+        Err(PngError::ChunkNotFound(chunk_type.clone()))
+    }
+
+    /// Remove the chunk at index `n` among the chunks of a type. The end chunk cannot be removed.
+    pub fn remove_nth_chunk(&mut self, chunk_type: &ChunkType, n: usize) -> Result<Chunk> {
+        self.check_removable(chunk_type)?;
+        let pos = self.my_chunks.iter().enumerate()
+            .filter(|(_, c)| c.chunk_type() == chunk_type)
+            .nth(n)
+            .map(|(i, _)| i)
+            .ok_or_else(|| PngError::ChunkNotFound(chunk_type.clone()))?;
+        Ok(self.my_chunks.remove(pos))
+    }
+
+    /// Remove every chunk of a type, returning the removed chunks in their original order and the
+    /// chunks dropped by the copy policy if the type is critical. The end chunk is never removed.
+    pub fn remove_all(&mut self, chunk_type: &ChunkType) -> (Vec<Chunk>, Vec<Chunk>) {
+        let end = self.container.end_chunk_type();
+        let (removed, kept): (Vec<Chunk>, Vec<Chunk>) = self.my_chunks.drain(..)
+            .partition(|c| c.chunk_type() == chunk_type && c.chunk_type() != end);
+        self.my_chunks = kept;
+        let dropped = self.after_edit(!removed.is_empty() && chunk_type.is_critical());
+        (removed, dropped)
+    }
+
+    /// Replace all chunks of a type.
+    /// The new chunks take the place of the first chunk of that type, or are appended if there was none.
+    /// If the type is critical, the copy policy is applied afterwards and any dropped chunks are returned.
+    pub fn replace_chunks(&mut self, chunk_type: &ChunkType, chunks: Vec<Chunk>) -> Vec<Chunk> {
+        let pos = self.my_chunks.iter().position(|c| c.chunk_type() == chunk_type);
+        self.my_chunks.retain(|c| c.chunk_type() != chunk_type);
+        match pos {
+            Some(idx) => {
+                self.my_chunks.splice(idx..idx, chunks);
+            }
+            None => {
+                chunks.into_iter().for_each(|c| self.append_chunk(c));
+            }
+        }
+
+        if chunk_type.is_critical() {
+            self.drop_unsafe_to_copy()
+        }
+        else {
+            Vec::new()
+        }
     }
 
-    // fn header(&self) -> &[u8; 8] {
-    //     &self.header
-    // }
+    /// Apply the copy policy after critical chunks have been modified.
+    /// Under [CopyPolicy::DropUnsafe], removes and returns the non-standard ancillary chunks
+    /// that are not safe to copy. Under [CopyPolicy::KeepAll], nothing is removed.
+    pub fn drop_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        match self.copy_policy {
+            CopyPolicy::KeepAll => Vec::new(),
+            CopyPolicy::DropUnsafe => {
+                self.strip_ancillary(|ct| ct.is_safe_to_copy() || ct.is_standard())
+            }
+        }
+    }
+
+    /// Strip ancillary chunks.
+    /// Removes every ancillary chunk for which `keep` returns false and returns the removed chunks
+    /// in their original order. Critical chunks are never removed.
+    pub fn strip_ancillary<F>(&mut self, keep: F) -> Vec<Chunk>
+        where F: Fn(&ChunkType) -> bool {
+        let mut removed = Vec::<Chunk>::new();
+        let mut kept = Vec::<Chunk>::with_capacity(self.my_chunks.len());
+        for c in self.my_chunks.drain(..) {
+            if c.chunk_type().is_critical() || keep(c.chunk_type()) {
+                kept.push(c);
+            }
+            else {
+                removed.push(c);
+            }
+        }
+        self.my_chunks = kept;
+        removed
+    }
 
     /// Return the set of chunks that make up the PNG.
-    pub(crate) fn chunks(&self) -> &[Chunk] {
+    pub fn chunks(&self) -> &[Chunk] {
         self.my_chunks.as_slice()
     }
 
@@ -74,23 +339,44 @@ impl Png {
         self.my_chunks.iter().find(|&c| c.chunk_type() == chunk_type)
     }
 
-    /// Return the PNG file represented as a vector of bytes.
-    fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = self.header.to_vec();
+    /// Get the parsed image header.
+    pub fn image_header(&self) -> Result<ImageHeader> {
+        match self.my_chunks.first() {
+            Some(c) if c.chunk_type() == ImageHeader::CHUNK_TYPE => ImageHeader::try_from(c),
+            _ => Err(PngError::MissingRequiredChunks(vec![ImageHeader::CHUNK_TYPE.to_string()])),
+        }
+    }
+
+    /// Return the PNG file represented as a vector of bytes, including any trailing data.
+    pub(crate) fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.container.signature().to_vec();
 
         self.my_chunks.iter().for_each(|c| bytes.append(&mut c.as_bytes()));
+        bytes.extend_from_slice(&self.trailing);
 
         bytes
     }
 
-    /// Load a PNG from file.
+    /// Load a PNG, MNG or JNG from file.
     pub fn load(filepath: impl AsRef<Path>) -> Result<Png> {
         let file_bytes = std::fs::read(filepath)?;
 
         Png::try_from(file_bytes.as_slice())
     }
 
+    /// Parse a PNG, MNG or JNG from bytes, keeping chunks whose CRC does not match.
+    /// Use [Chunk::has_valid_crc] to find them; [Png::try_from] rejects them.
+    pub fn from_raw(value: &[u8]) -> Result<Png> {
+        Png::parse(value, Chunk::from_raw)
+    }
+
+    /// Load a PNG, MNG or JNG from file, keeping chunks whose CRC does not match.
+    pub fn load_raw(filepath: impl AsRef<Path>) -> Result<Png> {
+        Png::from_raw(&std::fs::read(filepath)?)
+    }
+
     /// Write the current PNG to a file.
+    /// Trailing data is written after the end chunk; use [Png::take_trailing_data] to drop it.
     pub fn save(&self, filepath: impl AsRef<Path>) -> Result<()> {
         std::fs::write(filepath, self.as_bytes())?;
         Ok(())
@@ -98,51 +384,91 @@ impl Png {
 
 }
 
+/// A chunk of a [Png] whose data can be edited, but which cannot be replaced by another chunk.
+/// Dereferences to the chunk for reading.
+///
+/// Editing data through it does not apply the copy policy, even for critical chunks; call
+/// [Png::drop_unsafe_to_copy] after changing a critical chunk.
+pub struct ChunkMut<'a>(&'a mut Chunk);
+
+impl ChunkMut<'_> {
+    /// Replace the data of the chunk, as [Chunk::set_data].
+    pub fn set_data(&mut self, data: Vec<u8>) -> std::result::Result<(), ChunkError> {
+        self.0.set_data(data)
+    }
+
+    /// Edit the data of the chunk in place, as [Chunk::data_mut].
+    pub fn data_mut(&mut self) -> DataMut<'_> {
+        self.0.data_mut()
+    }
+
+    /// Recompute a mismatched CRC, as [Chunk::repair_crc].
+    pub fn repair_crc(&mut self) -> Option<u32> {
+        self.0.repair_crc()
+    }
+}
+
+impl std::ops::Deref for ChunkMut<'_> {
+    type Target = Chunk;
+
+    fn deref(&self) -> &Chunk {
+        self.0
+    }
+}
+
 impl TryFrom<&[u8]> for Png {
     type Error = PngError;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        if value[0..8].iter()
-            .zip(Png::STANDARD_HEADER)
-            .map(|(a, b)| a.cmp(&b))
-            .any(|o| o != std::cmp::Ordering::Equal)  {
-            return Err(PngError::BadHeader);
-        }
+        Png::parse(value, |bytes| Chunk::try_from(bytes))
+    }
+}
 
-        let mut new_png = Png {
-            header: Png::STANDARD_HEADER,
-            my_chunks: Vec::<Chunk>::new()
+impl Png {
+    /// Parse the header and chunks, using `parse_chunk` to read each chunk.
+    fn parse<F>(value: &[u8], parse_chunk: F) -> Result<Png>
+        where F: Fn(&[u8]) -> std::result::Result<Chunk, ChunkError> {
+        let container = match Container::from_signature(value) {
+            Some(c) => c,
+            None => return Err(PngError::BadHeader),
         };
 
+        let mut new_png = Png::from_container_chunks(container, Vec::<Chunk>::new());
+
         let mut idx: usize = 8;
-        let mut saw_ihdr = false;
-        let mut saw_idat = false;
+        let mut seen = vec![false; container.required_chunk_types().len()];
         loop {
-            let maybe_chunk = Chunk::try_from(&value[idx..]);
+            let maybe_chunk = parse_chunk(&value[idx..]);
             match maybe_chunk {
                 Ok(t_chunk) => {
                     idx += t_chunk.length() as usize + 12;
                     let ct = &t_chunk.chunk_type().clone();
                     new_png.append_chunk(t_chunk);
 
-                    if ct == &ChunkType::END_CHUNK {
+                    if ct == container.end_chunk_type() {
+                        new_png.trailing = value[idx..].to_vec();
                         break;
                     }
-                    saw_ihdr = saw_ihdr || ct == "IHDR";
-                    saw_idat = saw_idat || ct == "IDAT";
+                    for (s, &required) in seen.iter_mut().zip(container.required_chunk_types()) {
+                        *s = *s || ct == required;
+                    }
                 }
-                Err(chunk_err) => {
-                    return Err(chunk_err.into());
+                Err(source) => {
+                    return Err(PngError::BadChunk { index: new_png.my_chunks.len(), offset: idx, source });
                 }
             }
         };
 
         //An end chunk having been seen is implied; either we saw an end chunk and got here, or bailed on an error
-        if saw_ihdr && saw_idat  {
+        let missing: Vec<String> = seen.iter().zip(container.required_chunk_types())
+            .filter(|(&s, _)| !s)
+            .map(|(_, &t)| t.to_string())
+            .collect();
+        if missing.is_empty() {
              Ok(new_png)
         }
         else {
-            Err(PngError::MissingRequiredChunks)
+            Err(PngError::MissingRequiredChunks(missing))
         }
     }
 }
@@ -252,6 +578,30 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_error_context() {
+        let chunks = testing_chunks();
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend(chunks[0].as_bytes());
+        let second = bytes.len();
+        bytes.extend(chunks[1].as_bytes());
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+
+        match Png::try_from(bytes.as_slice()) {
+            Err(PngError::BadChunk { index: 1, offset, source: ChunkError::BadCRC { chunk_type, .. } }) => {
+                assert_eq!(offset, second);
+                assert_eq!(chunk_type, *"IDAT");
+            },
+            _ => panic!("expected a bad CRC in chunk 1"),
+        }
+
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend(chunks[0].as_bytes());
+        bytes.extend(chunks[5].as_bytes());
+        assert!(matches!(Png::try_from(bytes.as_slice()),
+                         Err(PngError::MissingRequiredChunks(missing)) if missing == vec!["IDAT".to_string()]));
+    }
 
     #[test]
     fn test_list_chunks() {
@@ -291,6 +641,199 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_strip_ancillary() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("teSt", "Message").unwrap());
+        let removed = png.strip_ancillary(|ct| ct == "miDl");
+        let removed: Vec<String> = removed.iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(removed, vec!["teSt".to_string()]);
+        assert_eq!(png.chunks().len(), testing_chunks().len());
+        assert!(png.chunk_by_type(&ChunkType::from_str("miDl").unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_replace_critical_drops_unsafe() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("maRK", "unsafe").unwrap());
+        png.append_chunk(chunk_from_strings("saFe", "safe").unwrap());
+        png.append_chunk(chunk_from_strings("gAMA", "known").unwrap());
+        let idat = ChunkType::from_str("IDAT").unwrap();
+        let dropped = png.replace_chunks(&idat, vec![chunk_from_strings("IDAT", "1").unwrap()]);
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(&dropped[0].chunk_type().to_string(), "maRK");
+        assert_eq!(&png.chunk_by_type(&idat).unwrap().as_string().unwrap(), "1");
+        assert!(png.chunk_by_type(&ChunkType::from_str("saFe").unwrap()).is_some());
+        assert!(png.chunk_by_type(&ChunkType::from_str("gAMA").unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_replace_critical_keep_all() {
+        let mut png = testing_png();
+        png.set_copy_policy(CopyPolicy::KeepAll);
+        png.append_chunk(chunk_from_strings("maRK", "unsafe").unwrap());
+        let idat = ChunkType::from_str("IDAT").unwrap();
+        let dropped = png.replace_chunks(&idat, vec![chunk_from_strings("IDAT", "1").unwrap()]);
+
+        assert!(dropped.is_empty());
+        assert!(png.chunk_by_type(&ChunkType::from_str("maRK").unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_mng_and_jng_from_bytes() {
+        let mng_chunks = vec![
+            chunk_from_strings("MHDR", "0000000000000000000000000000").unwrap(),
+            testing_chunks().remove(0),
+            chunk_from_strings("IDAT", "0").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+            chunk_from_strings("MEND", "").unwrap(),
+        ];
+        let jng_chunks = vec![
+            chunk_from_strings("JHDR", "0000000000000000").unwrap(),
+            chunk_from_strings("JDAT", "0").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ];
+
+        for (container, chunks) in [(Container::Mng, mng_chunks), (Container::Jng, jng_chunks)] {
+            let count = chunks.len();
+            let bytes: Vec<u8> = container.signature().into_iter()
+                .chain(chunks.into_iter().flat_map(|chunk| chunk.as_bytes()))
+                .collect();
+
+            let mut png = Png::try_from(bytes.as_ref()).unwrap();
+            assert_eq!(png.container(), container);
+            assert_eq!(png.chunks().len(), count);
+
+            png.append_chunk(chunk_from_strings("ruSt", "Message").unwrap());
+            assert_eq!(png.chunks()[count - 1].chunk_type(), "ruSt");
+            assert_eq!(png.as_bytes()[0..8], container.signature());
+        }
+    }
+
+    #[test]
+    fn test_remove_nth_and_all() {
+        let mut png = testing_png();
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        png.append_chunk(chunk_from_strings("ruSt", "First message").unwrap());
+        png.append_chunk(chunk_from_strings("ruSt", "Second message").unwrap());
+        png.append_chunk(chunk_from_strings("ruSt", "Third message").unwrap());
+
+        let removed = png.remove_nth_chunk(&rust, 1).unwrap();
+        assert_eq!(removed.as_string().unwrap(), "Second message");
+        assert!(png.remove_nth_chunk(&rust, 2).is_err());
+        assert!(matches!(png.remove_nth_chunk(&ChunkType::END_CHUNK, 0), Err(PngError::InvalidEdit(_))));
+        assert!(matches!(png.remove_chunk(&ChunkType::END_CHUNK), Err(PngError::InvalidEdit(_))));
+        assert_eq!(png.chunks().last().unwrap().chunk_type(), &ChunkType::END_CHUNK);
+
+        assert_eq!(png.remove_all(&rust).0.len(), 2);
+        assert!(png.chunk_by_type(&rust).is_none());
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut png = testing_png();
+        let types = |png: &Png| png.iter().map(|c| c.chunk_type().to_string()).collect::<Vec<String>>();
+        let idat = ChunkType::from_str("IDAT").unwrap();
+
+        png.insert_before(&idat, chunk_from_strings("gAMA", "").unwrap()).unwrap();
+        png.insert_after(&idat, chunk_from_strings("tEXt", "").unwrap()).unwrap();
+        png.insert_at(6, chunk_from_strings("ruSt", "").unwrap()).unwrap();
+        assert_eq!(types(&png), vec!["IHDR", "gAMA", "IDAT", "tEXt", "FrSt", "miDl", "ruSt", "LASt", "IEND"]);
+
+        png.move_chunk(3, 7).unwrap();
+        assert_eq!(png.replace(1, chunk_from_strings("sRGB", "").unwrap()).unwrap().0.chunk_type().to_string(), "gAMA");
+        png.retain(|c| c.chunk_type().is_critical());
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "FrSt", "LASt", "IEND"]);
+        assert_eq!(png.chunks_of_type(&idat).count(), 1);
+
+        assert!(matches!(png.insert_at(5, chunk_from_strings("ruSt", "").unwrap()), Err(PngError::InvalidEdit(_))));
+        assert!(matches!(png.insert_at(1, chunk_from_strings("IEND", "").unwrap()), Err(PngError::InvalidEdit(_))));
+        assert!(matches!(png.replace(4, chunk_from_strings("ruSt", "").unwrap()), Err(PngError::InvalidEdit(_))));
+        assert!(matches!(png.move_chunk(4, 0), Err(PngError::InvalidEdit(_))));
+        assert!(matches!(png.move_chunk(0, 4), Err(PngError::InvalidEdit(_))));
+        assert!(png.remove_all(&ChunkType::END_CHUNK).0.is_empty());
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "FrSt", "LASt", "IEND"]);
+
+        for mut chunk in png.iter_mut() {
+            if chunk.chunk_type() == "FrSt" {
+                chunk.data_mut().extend_from_slice(b"edited");
+            }
+        }
+        assert_eq!(png.chunk_by_type(&ChunkType::from_str("FrSt").unwrap()).unwrap().data(), b"I am the first chunkedited");
+    }
+
+    #[test]
+    fn test_editing_applies_copy_policy() {
+        let unsafe_chunk = || chunk_from_strings("ruST", "").unwrap();
+        let mut png = testing_png();
+        png.insert_at(1, unsafe_chunk()).unwrap();
+        assert!(png.insert_at(1, chunk_from_strings("tEXt", "").unwrap()).unwrap().is_empty());
+        assert_eq!(png.insert_at(1, chunk_from_strings("PLTE", "").unwrap()).unwrap().len(), 1);
+
+        png.insert_at(1, unsafe_chunk()).unwrap();
+        assert_eq!(png.replace(1, chunk_from_strings("tIME", "").unwrap()).unwrap().1.len(), 0);
+        png.insert_at(1, unsafe_chunk()).unwrap();
+        assert_eq!(png.move_chunk(2, 3).unwrap().len(), 0);
+        assert_eq!(png.move_chunk(2, 3).unwrap().len(), 1);
+
+        png.insert_at(1, unsafe_chunk()).unwrap();
+        assert!(png.retain(|c| c.chunk_type() != "tEXt").is_empty());
+        assert_eq!(png.retain(|c| c.chunk_type() != "PLTE").len(), 1);
+
+        png.insert_at(1, unsafe_chunk()).unwrap();
+        assert!(png.remove_all(&ChunkType::from_str("tEXt").unwrap()).1.is_empty());
+        let (removed, dropped) = png.remove_all(&ChunkType::from_str("IDAT").unwrap());
+        assert_eq!((removed.len(), dropped.len()), (1, 1));
+
+        png.set_copy_policy(CopyPolicy::KeepAll);
+        png.insert_at(1, unsafe_chunk()).unwrap();
+        assert!(png.retain(|c| c.chunk_type() != "IHDR").is_empty());
+        assert_eq!(png.chunks_of_type(&ChunkType::from_str("ruST").unwrap()).count(), 1);
+    }
+
+    #[test]
+    fn test_chunk_mut_skips_copy_policy() {
+        let mut png = testing_png();
+        png.insert_at(1, chunk_from_strings("ruST", "").unwrap()).unwrap();
+        for mut chunk in png.iter_mut().filter(|c| c.chunk_type() == "IHDR") {
+            chunk.set_data(b"1".to_vec()).unwrap();
+        }
+        assert_eq!(png.chunks_of_type(&ChunkType::from_str("ruST").unwrap()).count(), 1);
+        assert_eq!(png.drop_unsafe_to_copy().len(), 1);
+    }
+
+    #[test]
+    fn test_from_raw_keeps_bad_crc() {
+        let mut bytes = testing_png().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+
+        let png = Png::from_raw(bytes.as_slice()).unwrap();
+        let bad: Vec<String> = png.iter().filter(|c| !c.has_valid_crc()).map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(bad, vec!["IEND"]);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"after the end");
+
+        let mut png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.trailing_data(), b"after the end");
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.take_trailing_data(), b"after the end".to_vec());
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_short_header() {
+        assert!(matches!(Png::try_from(&Png::STANDARD_HEADER[0..4]), Err(PngError::BadHeader)));
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
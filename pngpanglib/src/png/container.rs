@@ -0,0 +1,70 @@
+//! Implements the members of the PNG family of formats.
+//!
+//! PNG, MNG and JNG share the same chunk layout and differ in their signature, end chunk and
+//! required chunks. See <http://www.libpng.org/pub/mng/spec/> for MNG and JNG.
+
+use std::fmt::{Display, Formatter};
+
+/// The container format of a chunk stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Container {
+    /// Portable Network Graphics.
+    #[default]
+    Png,
+    /// Multiple-image Network Graphics.
+    Mng,
+    /// JPEG Network Graphics.
+    Jng,
+}
+
+impl Container {
+    /// The signature found at the start of each PNG file.
+    pub const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    /// The signature found at the start of each MNG file.
+    pub const MNG_SIGNATURE: [u8; 8] = [138, 77, 78, 71, 13, 10, 26, 10];
+    /// The signature found at the start of each JNG file.
+    pub const JNG_SIGNATURE: [u8; 8] = [139, 74, 78, 71, 13, 10, 26, 10];
+
+    /// Identify the container from the first eight bytes of a file.
+    pub fn from_signature(bytes: &[u8]) -> Option<Container> {
+        let sig = bytes.get(0..8)?;
+        [Container::Png, Container::Mng, Container::Jng].into_iter()
+            .find(|c| c.signature() == sig)
+    }
+
+    /// Get the signature of the container.
+    pub fn signature(&self) -> [u8; 8] {
+        match self {
+            Container::Png => Container::PNG_SIGNATURE,
+            Container::Mng => Container::MNG_SIGNATURE,
+            Container::Jng => Container::JNG_SIGNATURE,
+        }
+    }
+
+    /// Get the chunk type that ends the datastream.
+    pub fn end_chunk_type(&self) -> &'static str {
+        match self {
+            Container::Png | Container::Jng => "IEND",
+            Container::Mng => "MEND",
+        }
+    }
+
+    /// Get the chunk types that must be present in a valid datastream.
+    pub fn required_chunk_types(&self) -> &'static [&'static str] {
+        match self {
+            Container::Png => &["IHDR", "IDAT"],
+            Container::Mng => &["MHDR"],
+            Container::Jng => &["JHDR", "JDAT"],
+        }
+    }
+}
+
+impl Display for Container {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Container::Png => write!(f, "PNG"),
+            Container::Mng => write!(f, "MNG"),
+            Container::Jng => write!(f, "JNG"),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Implements the typed contents of the IHDR chunk.
+
+use std::str::FromStr;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::png::PngError;
+
+/// The image header, describing dimensions and sample layout of the image data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageHeader {
+    /// Image width in pixels.
+    pub width: u32,
+    /// Image height in pixels.
+    pub height: u32,
+    /// Number of bits per sample or palette index.
+    pub bit_depth: u8,
+    /// Color type code (0, 2, 3, 4 or 6).
+    pub color_type: u8,
+    /// Compression method; only 0 (deflate) is defined.
+    pub compression_method: u8,
+    /// Filter method; only 0 (adaptive) is defined.
+    pub filter_method: u8,
+    /// Interlace method; 0 for none, 1 for Adam7.
+    pub interlace_method: u8,
+}
+
+impl ImageHeader {
+    /// The chunk type of the image header.
+    pub const CHUNK_TYPE: &'static str = "IHDR";
+
+    /// Get the number of samples per pixel.
+    pub fn channels(&self) -> Result<usize, PngError> {
+        match self.color_type {
+            0 | 3 => Ok(1),
+            2 => Ok(3),
+            4 => Ok(2),
+            6 => Ok(4),
+            _ => Err(PngError::MalformedChunk(ImageHeader::CHUNK_TYPE.to_string())),
+        }
+    }
+
+    /// Get the number of bits per pixel, validating the bit depth against the color type.
+    pub fn bits_per_pixel(&self) -> Result<usize, PngError> {
+        let allowed: &[u8] = match self.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            _ => &[8, 16],
+        };
+        if !allowed.contains(&self.bit_depth) {
+            return Err(PngError::MalformedChunk(ImageHeader::CHUNK_TYPE.to_string()));
+        }
+        Ok(self.channels()? * self.bit_depth as usize)
+    }
+
+    /// Get the number of bytes in a scanline of the given width, excluding the filter byte.
+    pub fn row_bytes(&self, width: u32) -> Result<usize, PngError> {
+        Ok((width as usize * self.bits_per_pixel()?).div_ceil(8))
+    }
+
+    /// Encode the header as an IHDR chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::<u8>::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.extend_from_slice(&[self.bit_depth, self.color_type, self.compression_method,
+            self.filter_method, self.interlace_method]);
+
+        Chunk::new(&ChunkType::from_str(ImageHeader::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for ImageHeader {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        if chunk.chunk_type() != ImageHeader::CHUNK_TYPE || data.len() != 13 {
+            return Err(PngError::MalformedChunk(chunk.chunk_type().to_string()));
+        }
+
+        Ok(ImageHeader {
+            width: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            height: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        })
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::fmt::{Debug, Formatter};
 
-use crate::chunk::ChunkError;
+use crate::chunk::{ChunkError, ChunkType, ChunkTypeError};
 
 /// Implements specific errors emitted by the PNG object.
 #[derive(Debug)]
@@ -10,26 +10,88 @@ pub enum PngError {
     /// Bad or missing magic bytes.
     BadHeader,
     /// Non-optional chunks are missing (see <http://www.libpng.org/pub/png/spec/1.2/>).
-    MissingRequiredChunks,
+    MissingRequiredChunks(Vec<String>),
     /// Chunk sought by caller was not found.
-    ChunkNotFound,
-    /// Other unspecified error.
-    GenericError,
+    ChunkNotFound(ChunkType),
     /// IO-specific error wrapper.
     IO(std::io::Error),
     /// Chunk-specific error wrapper.
     ChunkError(ChunkError),
+    /// A chunk could not be parsed from the file.
+    BadChunk {
+        /// Position of the chunk in the file, counting from 0.
+        index: usize,
+        /// Byte offset of the chunk from the start of the file.
+        offset: usize,
+        /// What was wrong with the chunk.
+        source: ChunkError,
+    },
+    /// A chunk type given by the caller is invalid.
+    ChunkTypeError(ChunkTypeError),
+    /// Chunk data does not match the layout required by its type.
+    MalformedChunk(String),
+    /// The image is not an animated PNG.
+    NotAnimated,
+    /// Requested animation frame does not exist.
+    FrameNotFound(usize),
+    /// Animation chunk sequence numbers are out of order.
+    BadSequence {
+        /// The sequence number that should have been found.
+        expected: u32,
+        /// The sequence number actually found.
+        found: u32,
+    },
+    /// The animation structure is inconsistent.
+    BadAnimation(String),
+    /// Image data could not be decompressed or unfiltered.
+    BadImageData(String),
+    /// The operation does not support this image.
+    Unsupported(String),
+    /// The payload does not fit in the image.
+    CapacityExceeded {
+        /// Number of bytes the image can hold.
+        capacity: usize,
+        /// Number of bytes needed.
+        needed: usize,
+    },
+    /// No hidden payload was found.
+    NoPayload,
+    /// A compressed payload could not be decompressed.
+    BadPayload(String),
+    /// A signature is missing its key, was made by another key or does not match.
+    BadSignature(String),
+    /// An edit would leave the chunks out of order, such as placing a chunk after the end chunk.
+    InvalidEdit(String),
 }
 
 impl std::fmt::Display for PngError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             PngError::BadHeader => write!(f, "Bad, incomplete, or missing header."),
-            PngError::MissingRequiredChunks => write!(f, "Missing required chunks."),
-            PngError::ChunkNotFound => write!(f, "Chunk not found."),
-            PngError::GenericError => write!(f, "Non-specific png error."),
+            PngError::MissingRequiredChunks(t) => write!(f, "Missing required chunks: {}.", t.join(", ")),
+            PngError::ChunkNotFound(t) => write!(f, "{t} chunk not found."),
             PngError::IO(e) => write!(f, "IO Error: {e}"),
             PngError::ChunkError(e) => {write!(f, "Chunk Error: {e}")},
+            PngError::BadChunk { index, offset, source } => {
+                write!(f, "Chunk {index} at offset {offset}: {source}")
+            },
+            PngError::ChunkTypeError(e) => write!(f, "{e}"),
+            PngError::MalformedChunk(t) => write!(f, "Malformed {t} chunk."),
+            PngError::NotAnimated => write!(f, "Not an animated PNG."),
+            PngError::FrameNotFound(i) => write!(f, "Frame {i} not found."),
+            PngError::BadSequence { expected, found } => {
+                write!(f, "Bad animation sequence number: expected {expected}, found {found}.")
+            },
+            PngError::BadAnimation(s) => write!(f, "Bad animation: {s}"),
+            PngError::BadImageData(s) => write!(f, "Bad image data: {s}"),
+            PngError::Unsupported(s) => write!(f, "Unsupported: {s}"),
+            PngError::CapacityExceeded { capacity, needed } => {
+                write!(f, "Payload of {needed} bytes exceeds capacity of {capacity} bytes.")
+            },
+            PngError::NoPayload => write!(f, "No payload found."),
+            PngError::BadPayload(s) => write!(f, "Bad payload: {s}"),
+            PngError::BadSignature(s) => write!(f, "Bad signature: {s}"),
+            PngError::InvalidEdit(s) => write!(f, "Invalid edit: {s}"),
         }
     }
 }
@@ -40,9 +102,9 @@ impl From<std::io::Error> for PngError {
     }
 }
 
-impl From<()> for PngError {
-    fn from(_: ()) -> Self {
-        PngError::GenericError
+impl From<ChunkTypeError> for PngError {
+    fn from(value: ChunkTypeError) -> Self {
+        PngError::ChunkTypeError(value)
     }
 }
 
@@ -53,5 +115,12 @@ impl From<ChunkError> for PngError {
 }
 
 impl std::error::Error for PngError {
-
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PngError::IO(e) => Some(e),
+            PngError::ChunkError(e) | PngError::BadChunk { source: e, .. } => Some(e),
+            PngError::ChunkTypeError(e) => Some(e),
+            _ => None,
+        }
+    }
 }
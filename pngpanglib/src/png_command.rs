@@ -3,9 +3,23 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
+use crate::apng::{ApngBuilder, BlendOp, DisposeOp, FrameOptions, ACTL, FCTL, FDAT};
 use crate::chunk::Chunk;
 use crate::chunk::ChunkType;
-use crate::png::{Png, PngError};
+use crate::compare;
+use crate::diff;
+use crate::image::EncodeOptions;
+use crate::message::{self, Selector};
+use crate::optimize::{self, OptimizeOptions};
+use crate::payload::{self, PayloadCodec};
+use crate::png::{CopyPolicy, Png, PngError};
+use crate::registry;
+use crate::scan;
+use crate::sign;
+use crate::stego::lsb::{self, Channels, LsbConfig};
+use crate::stego::palette;
+use crate::stego::stream::{self, StreamMode};
+use crate::stego::watermark::{self, WatermarkConfig};
 
 #[cfg(feature="clap")]
 use clap::Subcommand;
@@ -24,6 +38,27 @@ pub enum PngCommand {
         message: String,
         /// Output file if different from source.
         out_filename: Option<PathBuf>,
+        /// For animated PNGs, place the message after the data of this frame.
+        #[cfg_attr(feature="clap", arg(long))]
+        frame: Option<usize>,
+        /// With chunk type IDAT, hide the message in the image stream: trailer (the default), extra-idat or stored-block.
+        #[cfg_attr(feature="clap", arg(long))]
+        stream_mode: Option<String>,
+        /// Compress the message before embedding: none, deflate, zstd or brotli.
+        #[cfg_attr(feature="clap", arg(long, default_value = "none"))]
+        compress: String,
+        /// Shared secret for an HMAC-SHA256 tag over the chunk type and message.
+        #[cfg_attr(feature="clap", arg(long))]
+        secret: Option<String>,
+        /// Label stored with the message, so several messages can share a chunk type.
+        #[cfg_attr(feature="clap", arg(long))]
+        label: Option<String>,
+        /// Keep existing messages with the same chunk type and label instead of replacing them.
+        #[cfg_attr(feature="clap", arg(long))]
+        append: bool,
+        /// Write the chunk even if its type is critical or sets the reserved bit.
+        #[cfg_attr(feature="clap", arg(long))]
+        force: bool,
     },
     /// Decode a message with the given chunk type.
     Decode {
@@ -31,6 +66,18 @@ pub enum PngCommand {
         filename: PathBuf,
         /// Chunk type code. See PNG spec at <http://www.libpng.org/pub/png/spec/1.2/> for details.
         chunk_type: String,
+        /// Shared secret to verify the HMAC-SHA256 tag of the message.
+        #[cfg_attr(feature="clap", arg(long))]
+        secret: Option<String>,
+        /// List every message with the chunk type.
+        #[cfg_attr(feature="clap", arg(long, conflicts_with_all = ["index", "label"]))]
+        all: bool,
+        /// Decode the message at this index among the chunks of the type.
+        #[cfg_attr(feature="clap", arg(long, conflicts_with = "label"))]
+        index: Option<usize>,
+        /// Decode the first message with this label.
+        #[cfg_attr(feature="clap", arg(long))]
+        label: Option<String>,
     },
     /// Remove the message with the given chunk type.
     Remove {
@@ -40,15 +87,251 @@ pub enum PngCommand {
         chunk_type: String,
         /// Output file if different from source.
         out_filename: Option<PathBuf>,
+        /// Remove every message with the chunk type.
+        #[cfg_attr(feature="clap", arg(long, conflicts_with_all = ["index", "label"]))]
+        all: bool,
+        /// Remove the message at this index among the chunks of the type.
+        #[cfg_attr(feature="clap", arg(long, conflicts_with = "label"))]
+        index: Option<usize>,
+        /// Remove the first message with this label.
+        #[cfg_attr(feature="clap", arg(long))]
+        label: Option<String>,
     },
-    /// Generate a list of chunk types and their counts.
+    /// List the chunk types with their counts and names, and report chunks out of order.
     Print {
         /// Path to source PNG file.
         filename: PathBuf,
-    }
+    },
+    /// List the frames of an animated PNG.
+    Frames {
+        /// Path to source PNG file.
+        filename: PathBuf,
+    },
+    /// Extract a frame of an animated PNG as a standalone PNG.
+    ExtractFrame {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Index of the frame, starting at 0.
+        index: usize,
+        /// Output file.
+        out_filename: PathBuf,
+    },
+    /// Assemble an animated PNG from still PNGs of equal dimensions.
+    Assemble {
+        /// Output file.
+        out_filename: PathBuf,
+        /// Paths to the frame PNG files, in order.
+        #[cfg_attr(feature="clap", arg(required = true))]
+        inputs: Vec<PathBuf>,
+        /// Frame delay in milliseconds. Give once for all frames or once per frame.
+        #[cfg_attr(feature="clap", arg(long))]
+        delay: Vec<u16>,
+        /// Number of times to play the animation; 0 loops forever.
+        #[cfg_attr(feature="clap", arg(long, default_value_t = 0))]
+        loops: u32,
+        /// Dispose op (none, background, previous). Give once for all frames or once per frame.
+        #[cfg_attr(feature="clap", arg(long))]
+        dispose: Vec<String>,
+        /// Blend op (source, over). Give once for all frames or once per frame.
+        #[cfg_attr(feature="clap", arg(long))]
+        blend: Vec<String>,
+    },
+    /// Hide a message in the least significant bits of the pixel data.
+    LsbEncode {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Message to encode.
+        message: String,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Number of low bits used in each sample, 1 to 8.
+        #[cfg_attr(feature="clap", arg(long, default_value_t = 1))]
+        bits: u8,
+        /// Channels to use, any of r, g, b and a. For grayscale images, r, g or b select gray.
+        #[cfg_attr(feature="clap", arg(long, default_value = "rgb"))]
+        channels: String,
+        /// Passphrase seeding the pixel order.
+        #[cfg_attr(feature="clap", arg(long))]
+        passphrase: Option<String>,
+        /// Compress the message before embedding: none, deflate, zstd or brotli.
+        #[cfg_attr(feature="clap", arg(long, default_value = "none"))]
+        compress: String,
+        /// Keep ancillary chunks that are not safe to copy when the image data changes.
+        #[cfg_attr(feature="clap", arg(long))]
+        keep_unsafe: bool,
+    },
+    /// Recover a message hidden in the least significant bits of the pixel data.
+    LsbDecode {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Number of low bits used in each sample, 1 to 8.
+        #[cfg_attr(feature="clap", arg(long, default_value_t = 1))]
+        bits: u8,
+        /// Channels to use, any of r, g, b and a. For grayscale images, r, g or b select gray.
+        #[cfg_attr(feature="clap", arg(long, default_value = "rgb"))]
+        channels: String,
+        /// Passphrase seeding the pixel order.
+        #[cfg_attr(feature="clap", arg(long))]
+        passphrase: Option<String>,
+    },
+    /// Report how many bytes can be hidden in the pixel data.
+    LsbCapacity {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Number of low bits used in each sample, 1 to 8.
+        #[cfg_attr(feature="clap", arg(long, default_value_t = 1))]
+        bits: u8,
+        /// Channels to use, any of r, g, b and a. For grayscale images, r, g or b select gray.
+        #[cfg_attr(feature="clap", arg(long, default_value = "rgb"))]
+        channels: String,
+    },
+    /// Hide a message in the palette order of an indexed image, leaving the rendered image unchanged.
+    PaletteEncode {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Message to encode.
+        message: String,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Compress the message before embedding: none, deflate, zstd or brotli.
+        #[cfg_attr(feature="clap", arg(long, default_value = "none"))]
+        compress: String,
+        /// Keep ancillary chunks that are not safe to copy when the image data changes.
+        #[cfg_attr(feature="clap", arg(long))]
+        keep_unsafe: bool,
+    },
+    /// Recover a message hidden in the palette order of an indexed image.
+    PaletteDecode {
+        /// Path to source PNG file.
+        filename: PathBuf,
+    },
+    /// Report how many bytes can be hidden in the palette order of an indexed image.
+    PaletteCapacity {
+        /// Path to source PNG file.
+        filename: PathBuf,
+    },
+    /// Scan for content that may hide a payload and report a score for each finding.
+    Scan {
+        /// Path to source PNG file.
+        filename: PathBuf,
+    },
+    /// Show the data following the end chunk.
+    TrailingRead {
+        /// Path to source PNG file.
+        filename: PathBuf,
+    },
+    /// Replace the data following the end chunk with a message.
+    TrailingWrite {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Message to write after the end chunk.
+        message: String,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+    },
+    /// Remove the data following the end chunk.
+    TrailingStrip {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+    },
+    /// Compare two files chunk by chunk and list added, removed, moved and modified chunks.
+    Diff {
+        /// Path to the original PNG file.
+        old_filename: PathBuf,
+        /// Path to the changed PNG file.
+        new_filename: PathBuf,
+    },
+    /// Compare the decoded pixels of two files and report how much they differ.
+    Compare {
+        /// Path to the original PNG file.
+        old_filename: PathBuf,
+        /// Path to the changed PNG file.
+        new_filename: PathBuf,
+        /// Write an image highlighting the differing pixels in red to this file.
+        #[cfg_attr(feature="clap", arg(long))]
+        diff_image: Option<PathBuf>,
+    },
+    /// Losslessly shrink the image data, trying filters, compression levels and smaller formats.
+    Optimize {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Also drop ancillary chunks that do not affect rendering, such as text and tIME.
+        #[cfg_attr(feature="clap", arg(long))]
+        strip: bool,
+        /// Also drop non-standard ancillary chunks, data after the end chunk and data hidden in the image stream.
+        #[cfg_attr(feature="clap", arg(long))]
+        strip_hidden: bool,
+        /// Keep the color type and bit depth.
+        #[cfg_attr(feature="clap", arg(long))]
+        keep_format: bool,
+    },
+    /// Rewrite chunk CRCs that do not match their data, reporting each fix.
+    Repair {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Only repair chunks of these types; may be given several times.
+        #[cfg_attr(feature="clap", arg(long))]
+        only: Vec<String>,
+    },
+    /// Generate an Ed25519 key pair for signing, written as hexadecimal.
+    Keygen {
+        /// Output file for the secret signing key.
+        secret_filename: PathBuf,
+        /// Output file for the public verifying key.
+        public_filename: PathBuf,
+    },
+    /// Sign the critical chunks and chosen ancillary chunks with an Ed25519 key.
+    Sign {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// File holding the secret signing key.
+        key_filename: PathBuf,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Ancillary chunk type to cover. May be given more than once.
+        #[cfg_attr(feature="clap", arg(long))]
+        include: Vec<String>,
+    },
+    /// Verify a signature and report which covered chunks changed.
+    Verify {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// File holding the public verifying key.
+        key_filename: PathBuf,
+    },
+    /// Embed or detect a robust watermark in the pixels.
+    Watermark {
+        /// Watermark operation.
+        #[cfg_attr(feature="clap", command(subcommand))]
+        command: WatermarkCommand,
+    },
+    /// Remove ancillary chunks, keeping critical chunks and an allowlist.
+    /// Without a preset, chunks that affect how the image renders (tRNS, gAMA, cHRM, sRGB, iCCP, sBIT) are kept.
+    Strip {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Additional chunk type to keep. May be given more than once.
+        #[cfg_attr(feature="clap", arg(long))]
+        keep: Vec<String>,
+        /// Only drop text, eXIf, tIME and private chunks.
+        #[cfg_attr(feature="clap", arg(long, conflicts_with = "minimal"))]
+        privacy: bool,
+        /// Drop every ancillary chunk not explicitly kept.
+        #[cfg_attr(feature="clap", arg(long))]
+        minimal: bool,
+    },
 }
 
 /// Hashmap definition for SuccessHashMap
+#[deprecated(note = "no command returns a hashmap")]
 pub type PngCmdSuccessHash = HashMap<String, u32>;
 
 /// Possible successful outcomes.
@@ -60,70 +343,539 @@ pub enum PngCmdSuccess {
     /// Success with a byte vector result.
     SuccessBytes(Vec<u8>),
     /// Success with a key-value hashmap output.
+    #[deprecated(note = "no command returns a hashmap")]
+    #[allow(deprecated)]
     SuccessHashMap(PngCmdSuccessHash),
 }
 
-impl From<()> for PngCmdSuccess {
-    fn from(_: ()) -> Self {
-        PngCmdSuccess::Success
-    }
+
+
+#[cfg_attr(feature="clap", derive(Subcommand))]
+/// Watermark operations and their arguments
+#[derive(Debug)]
+pub enum WatermarkCommand {
+    /// Embed a 32-bit identifier as a spread-spectrum watermark keyed by a secret.
+    Embed {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Identifier to embed.
+        id: u32,
+        /// Secret seeding the watermark pattern.
+        secret: String,
+        /// Output file if different from source.
+        out_filename: Option<PathBuf>,
+        /// Amplitude of the pattern, in units of an 8-bit sample.
+        #[cfg_attr(feature="clap", arg(long, default_value_t = 2.0))]
+        strength: f64,
+        /// Keep ancillary chunks that are not safe to copy when the image data changes.
+        #[cfg_attr(feature="clap", arg(long))]
+        keep_unsafe: bool,
+    },
+    /// Look for a watermark keyed by a secret and report the detection confidence.
+    Detect {
+        /// Path to source PNG file.
+        filename: PathBuf,
+        /// Secret seeding the watermark pattern.
+        secret: String,
+    },
 }
 
+impl WatermarkCommand {
+    /// Run the watermark command.
+    pub fn run(&self) -> Result<PngCmdSuccess, PngError> {
+        match self {
+            WatermarkCommand::Embed { filename, id, secret, out_filename, strength, keep_unsafe } => {
+                let mut png = Png::load(filename)?;
+                if *keep_unsafe {
+                    png.set_copy_policy(CopyPolicy::KeepAll);
+                }
+                let config = WatermarkConfig { secret: secret.clone(), strength: *strength };
+                let dropped = watermark::embed(&mut png, *id, &config, &EncodeOptions::default())?;
 
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+                let mut report = format!("Embedded watermark {id}.");
+                append_dropped(&mut report, &dropped);
+                Ok(PngCmdSuccess::SuccessMsg(report))
+            }
+            WatermarkCommand::Detect { filename, secret } => {
+                let png = Png::load(filename)?;
+                let detection = watermark::detect(&png, &WatermarkConfig::new(secret))?;
+                if detection.is_present() {
+                    Ok(PngCmdSuccess::SuccessMsg(format!("Watermark {} detected with confidence {:.1}.",
+                                                         detection.id, detection.confidence)))
+                }
+                else {
+                    Ok(PngCmdSuccess::SuccessMsg(format!("No watermark detected (confidence {:.1}, threshold {:.1}).",
+                                                         detection.confidence, watermark::DETECTION_THRESHOLD)))
+                }
+            }
+        }
+    }
+}
 
 impl PngCommand {
     /// Execute the command contained and return the outcome.
     pub fn exec(command: &PngCommand) -> Result<PngCmdSuccess, PngError> {
         match command {
-            PngCommand::Encode {filename, chunk_type, message, out_filename} => {
+            PngCommand::Encode {filename, chunk_type, message, out_filename, frame, stream_mode, compress, secret, label, append, force} => {
                 let mut png = Png::load(filename)?;
                 let ct = ChunkType::from_str(chunk_type)?;
-                let new_chunk = Chunk::new(&ct, message.as_bytes().to_vec());
-                let _ = png.remove_chunk(&ct); //try to remove the chunk if it exists; ignore error if it doesn't
-                png.append_chunk(new_chunk);
-
+                let packed = payload::pack(message.as_bytes(), PayloadCodec::from_str(compress)?);
                 let out_f = match out_filename {
                     None => filename,
                     Some(out) => out,
                 };
-                Ok(png.save(out_f)?.into())
+                if ct == *"IDAT" {
+                    let chunk_options = [("--secret", secret.is_some()), ("--label", label.is_some()),
+                                         ("--append", *append), ("--frame", frame.is_some()), ("--force", *force)];
+                    if let Some((option, _)) = chunk_options.iter().find(|(_, given)| *given) {
+                        return Err(PngError::Unsupported(format!("{option} with image stream messages")));
+                    }
+                    let mode = StreamMode::from_str(stream_mode.as_deref().unwrap_or("trailer"))?;
+                    let level = EncodeOptions::default().compression;
+                    let dropped = stream::embed(&mut png, &packed, mode, level)?;
+                    png.save(out_f)?;
+                    return Ok(PngCmdSuccess::SuccessMsg(embed_report(packed.len(), &dropped)));
+                }
+
+                if stream_mode.is_some() {
+                    return Err(PngError::Unsupported("--stream-mode with chunk types other than IDAT".to_string()));
+                }
+                if !*force {
+                    ct.check_addable()?;
+                }
+                let data = message::labeled(label.as_deref(), packed)?;
+                let new_chunk = match secret {
+                    None => Chunk::new(&ct, data),
+                    Some(secret) => Chunk::new_authenticated(&ct, data, secret.as_bytes()),
+                };
+                if !*append {
+                    message::remove_labeled(&mut png, &ct, label.as_deref());
+                }
+                match frame {
+                    None => png.append_chunk(new_chunk),
+                    Some(index) => png.insert_after_frame(*index, new_chunk)?,
+                }
+                if png.is_animated() {
+                    png.validate_animation()?;
+                }
+
+                png.save(out_f)?;
+                Ok(PngCmdSuccess::Success)
             }
-            PngCommand::Decode {filename, chunk_type } => {
+            PngCommand::Decode {filename, chunk_type, secret, all, index, label } => {
                 let png = Png::load(filename)?;
                 let ct = ChunkType::from_str(chunk_type)?;
+                if ct == *"IDAT" {
+                    if secret.is_some() {
+                        return Err(PngError::Unsupported("authentication tags on image stream messages".to_string()));
+                    }
+                    return payload_result(stream::extract(&png)?);
+                }
+                let secret = secret.as_ref().map(|s| s.as_bytes());
 
-                match png.chunk_by_type(&ct) {
-                    None => { Err(PngError::ChunkNotFound) }
-                    Some(chunk) => {
-                        if let Ok(cs) = chunk.as_string() {
-                            Ok(PngCmdSuccess::SuccessMsg(cs))
-                        }
-                        else {
-                            Ok(PngCmdSuccess::SuccessBytes(chunk.as_bytes()))
-                        }
+                if *all {
+                    let messages = message::messages(&png, &ct);
+                    if messages.is_empty() {
+                        return Err(PngError::ChunkNotFound(ct));
                     }
+                    let lines: Vec<String> = messages.iter()
+                        .map(|m| {
+                            let content = match m.content(secret) {
+                                Ok(c) => String::from_utf8_lossy(&c).to_string(),
+                                Err(e) => format!("({e})"),
+                            };
+                            match m.label() {
+                                None => format!("{}: {content}", m.index),
+                                Some(l) => format!("{} [{l}]: {content}", m.index),
+                            }
+                        })
+                        .collect();
+                    return Ok(PngCmdSuccess::SuccessMsg(lines.join("\n")));
                 }
+
+                let selector = Selector::new(*index, label.as_deref());
+                payload_result(message::find(&png, &ct, &selector)?.content(secret)?)
             }
-            PngCommand::Remove { filename, chunk_type, out_filename } => {
+            PngCommand::Remove { filename, chunk_type, out_filename, all, index, label } => {
                 let mut png = Png::load(filename)?;
                 let ct = ChunkType::from_str(chunk_type)?;
-                png.remove_chunk(&ct)?;
+                let mut dropped = Vec::<Chunk>::new();
+                if ct == *"IDAT" {
+                    dropped = stream::remove(&mut png, EncodeOptions::default().compression)?.1;
+                }
+                else if *all {
+                    let (removed, mut policy_dropped) = png.remove_all(&ct);
+                    if removed.is_empty() {
+                        return Err(PngError::ChunkNotFound(ct));
+                    }
+                    dropped.append(&mut policy_dropped);
+                }
+                else {
+                    message::remove(&mut png, &ct, &Selector::new(*index, label.as_deref()))?;
+                }
 
                 let out_f = match out_filename {
                     None => filename,
                     Some(out) => out,
                 };
-                Ok(png.save(out_f)?.into())
+                png.save(out_f)?;
+                if dropped.is_empty() {
+                    return Ok(PngCmdSuccess::Success);
+                }
+                let mut report = format!("Removed {ct}.");
+                append_dropped(&mut report, &dropped);
+                Ok(PngCmdSuccess::SuccessMsg(report))
             }
             PngCommand::Print { filename } => {
                 let png = Png::load(filename)?;
-                let mut hashmap = PngCmdSuccessHash::new();
+                let mut counts = Vec::<(&ChunkType, u32)>::new();
                 for chunk in png.chunks() {
-                    hashmap.entry(chunk.chunk_type().to_string()).and_modify(|ctr| *ctr += 1).or_insert(1);
+                    match counts.iter_mut().find(|(t, _)| *t == chunk.chunk_type()) {
+                        Some((_, ctr)) => *ctr += 1,
+                        None => counts.push((chunk.chunk_type(), 1)),
+                    }
+                }
+
+                let mut report: Vec<String> = counts.iter()
+                    .map(|(t, n)| format!("{t}  x{n}  {}", registry::friendly_name(t)))
+                    .collect();
+                let problems = registry::check_order(&png);
+                if !problems.is_empty() {
+                    report.push("Ordering problems:".to_string());
+                    report.extend(problems.iter().map(|p| format!("  {p}")));
+                }
+                Ok(PngCmdSuccess::SuccessMsg(report.join("\n")))
+            }
+            PngCommand::Frames { filename } => {
+                let png = Png::load(filename)?;
+                let actl = png.animation_control()?;
+                png.validate_animation()?;
+
+                let plays = match actl.num_plays {
+                    0 => "forever".to_string(),
+                    n => format!("{n} times"),
+                };
+                let mut report = format!("{} frames, plays {plays}", actl.num_frames);
+                for (i, frame) in png.frames().enumerate() {
+                    let frame = frame?;
+                    let fc = &frame.control;
+                    report.push_str(&format!("\n  {i}: {}x{} at ({}, {})  delay: {} ms  dispose: {:?}  blend: {:?}  data: {} bytes{}",
+                        fc.width, fc.height, fc.x_offset, fc.y_offset, fc.delay().as_millis(),
+                        fc.dispose_op, fc.blend_op, frame.data.len(),
+                        if frame.is_default_image { "  (default image)" } else { "" }));
+                }
+                Ok(PngCmdSuccess::SuccessMsg(report))
+            }
+            PngCommand::ExtractFrame { filename, index, out_filename } => {
+                let png = Png::load(filename)?;
+                png.extract_frame(*index)?.save(out_filename)?;
+                Ok(PngCmdSuccess::Success)
+            }
+            PngCommand::Assemble { out_filename, inputs, delay, loops, dispose, blend } => {
+                let n = inputs.len();
+                let delays = per_frame(delay, n, 100)?;
+                let disposes = per_frame(dispose, n, "none".to_string())?;
+                let blends = per_frame(blend, n, "source".to_string())?;
+
+                let mut builder = ApngBuilder::new().num_plays(*loops);
+                for (i, input) in inputs.iter().enumerate() {
+                    builder = builder.frame(Png::load(input)?, FrameOptions {
+                        delay_num: delays[i],
+                        delay_den: 1000,
+                        dispose_op: DisposeOp::from_str(&disposes[i])?,
+                        blend_op: BlendOp::from_str(&blends[i])?,
+                    });
                 }
+                builder.build()?.save(out_filename)?;
+                Ok(PngCmdSuccess::Success)
+            }
+            PngCommand::LsbEncode { filename, message, out_filename, bits, channels, passphrase, compress, keep_unsafe } => {
+                let mut png = Png::load(filename)?;
+                if *keep_unsafe {
+                    png.set_copy_policy(CopyPolicy::KeepAll);
+                }
+                let config = LsbConfig {
+                    bits_per_channel: *bits,
+                    channels: Channels::from_str(channels)?,
+                    passphrase: passphrase.clone(),
+                };
+                let packed = payload::pack(message.as_bytes(), PayloadCodec::from_str(compress)?);
+                let dropped = lsb::embed(&mut png, &packed, &config, &EncodeOptions::default())?;
 
-                Ok(PngCmdSuccess::SuccessHashMap(hashmap))
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+                Ok(PngCmdSuccess::SuccessMsg(embed_report(packed.len(), &dropped)))
+            }
+            PngCommand::LsbDecode { filename, bits, channels, passphrase } => {
+                let png = Png::load(filename)?;
+                let config = LsbConfig {
+                    bits_per_channel: *bits,
+                    channels: Channels::from_str(channels)?,
+                    passphrase: passphrase.clone(),
+                };
+                payload_result(lsb::extract(&png, &config)?)
+            }
+            PngCommand::LsbCapacity { filename, bits, channels } => {
+                let png = Png::load(filename)?;
+                let config = LsbConfig {
+                    bits_per_channel: *bits,
+                    channels: Channels::from_str(channels)?,
+                    passphrase: None,
+                };
+                let capacity = lsb::image_capacity(&png, &config)?;
+                Ok(PngCmdSuccess::SuccessMsg(format!("Capacity: {capacity} bytes")))
+            }
+            PngCommand::PaletteEncode { filename, message, out_filename, compress, keep_unsafe } => {
+                let mut png = Png::load(filename)?;
+                if *keep_unsafe {
+                    png.set_copy_policy(CopyPolicy::KeepAll);
+                }
+                let packed = payload::pack(message.as_bytes(), PayloadCodec::from_str(compress)?);
+                let dropped = palette::embed(&mut png, &packed, &EncodeOptions::default())?;
+
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+                Ok(PngCmdSuccess::SuccessMsg(embed_report(packed.len(), &dropped)))
+            }
+            PngCommand::PaletteDecode { filename } => {
+                let png = Png::load(filename)?;
+                payload_result(palette::extract(&png)?)
+            }
+            PngCommand::PaletteCapacity { filename } => {
+                let png = Png::load(filename)?;
+                let capacity = palette::capacity(&png)?;
+                Ok(PngCmdSuccess::SuccessMsg(format!("Capacity: {capacity} bytes")))
+            }
+            PngCommand::Scan { filename } => {
+                let bytes = std::fs::read(filename)?;
+                Ok(PngCmdSuccess::SuccessMsg(scan::scan_bytes(&bytes)?.to_string()))
+            }
+            PngCommand::TrailingRead { filename } => {
+                let png = Png::load(filename)?;
+                if png.trailing_data().is_empty() {
+                    return Err(PngError::NoPayload);
+                }
+                payload_result(png.trailing_data().to_vec())
+            }
+            PngCommand::TrailingWrite { filename, message, out_filename } => {
+                let mut png = Png::load(filename)?;
+                png.set_trailing_data(message.as_bytes().to_vec());
+
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+                Ok(PngCmdSuccess::Success)
+            }
+            PngCommand::TrailingStrip { filename, out_filename } => {
+                let mut png = Png::load(filename)?;
+                let removed = png.take_trailing_data();
+
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+                Ok(PngCmdSuccess::SuccessMsg(format!("Removed {} bytes after the end chunk", removed.len())))
+            }
+            PngCommand::Diff { old_filename, new_filename } => {
+                let report = diff::diff(&Png::load_raw(old_filename)?, &Png::load_raw(new_filename)?);
+                Ok(PngCmdSuccess::SuccessMsg(report.to_string()))
+            }
+            PngCommand::Compare { old_filename, new_filename, diff_image } => {
+                let comparison = compare::compare(&Png::load(old_filename)?, &Png::load(new_filename)?)?;
+                if let Some(path) = diff_image {
+                    comparison.visualize()?.save(path)?;
+                }
+                Ok(PngCmdSuccess::SuccessMsg(comparison.to_string()))
+            }
+            PngCommand::Optimize { filename, out_filename, strip, strip_hidden, keep_format } => {
+                let mut png = Png::load(filename)?;
+                let size_before = png.as_bytes().len();
+
+                let mut removed = Vec::<Chunk>::new();
+                if *strip {
+                    removed.append(&mut png.strip_ancillary(|ct| {
+                        !ct.is_standard() || RENDERING_TYPES.iter().chain(&ANIMATION_TYPES).any(|&t| ct == t)
+                    }));
+                }
+                let mut trailing = 0;
+                if *strip_hidden {
+                    removed.append(&mut png.strip_ancillary(|ct| ct.is_standard()));
+                    trailing = png.take_trailing_data().len();
+                }
+                let options = OptimizeOptions { reduce: !keep_format, discard_stream_payload: *strip_hidden };
+                let report = optimize::optimize(&mut png, &options)?;
+
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+
+                let mut lines = vec![report.to_string()];
+                if !removed.is_empty() {
+                    let types: Vec<String> = removed.iter().map(|c| c.chunk_type().to_string()).collect();
+                    lines.push(format!("Removed chunks: {}", types.join(", ")));
+                }
+                if trailing > 0 {
+                    lines.push(format!("Removed {trailing} bytes after the end chunk"));
+                }
+                let size_after = png.as_bytes().len();
+                let saved = size_before.saturating_sub(size_after);
+                lines.push(format!("File size: {size_before} -> {size_after} bytes, saved {saved} ({:.1}%)",
+                                   100.0 * saved as f64 / size_before as f64));
+                Ok(PngCmdSuccess::SuccessMsg(lines.join("\n")))
+            }
+            PngCommand::Repair { filename, out_filename, only } => {
+                let mut png = Png::load_raw(filename)?;
+                let only = only.iter()
+                    .map(|t| ChunkType::from_str(t))
+                    .collect::<Result<Vec<ChunkType>, _>>()?;
+
+                let mut report = Vec::<String>::new();
+                let mut skipped = 0;
+                for (i, mut chunk) in png.iter_mut().enumerate() {
+                    if !only.is_empty() && !only.contains(chunk.chunk_type()) {
+                        skipped += !chunk.has_valid_crc() as usize;
+                        continue;
+                    }
+                    if let Some(old) = chunk.repair_crc() {
+                        report.push(format!("chunk {i} ({}): CRC {old:08X} -> {:08X}", chunk.chunk_type(), chunk.stored_crc()));
+                    }
+                }
+                // Rewrite the input only if something changed, but always write a requested output
+                match out_filename {
+                    Some(out) => png.save(out)?,
+                    None if !report.is_empty() => png.save(filename)?,
+                    None => {},
+                }
+                if report.is_empty() {
+                    report.push("No bad CRCs repaired".to_string());
+                }
+                if skipped > 0 {
+                    report.push(format!("{skipped} bad CRCs left in chunks of other types"));
+                }
+                Ok(PngCmdSuccess::SuccessMsg(report.join("\n")))
+            }
+            PngCommand::Keygen { secret_filename, public_filename } => {
+                let key = sign::generate_key()?;
+                std::fs::write(secret_filename, sign::to_hex(key.as_bytes()))?;
+                std::fs::write(public_filename, sign::to_hex(key.verifying_key().as_bytes()))?;
+                Ok(PngCmdSuccess::SuccessMsg(format!("Generated key {}", sign::to_hex(&sign::key_id(&key.verifying_key())))))
+            }
+            PngCommand::Sign { filename, key_filename, out_filename, include } => {
+                let mut png = Png::load(filename)?;
+                let key = sign::signing_key_from_hex(&std::fs::read_to_string(key_filename)?)?;
+                let include = include.iter()
+                    .map(|t| ChunkType::from_str(t))
+                    .collect::<Result<Vec<ChunkType>, _>>()?;
+                let count = sign::sign(&mut png, &key, &include)?;
+
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+                Ok(PngCmdSuccess::SuccessMsg(format!("Signed {count} chunks with key {}",
+                                                     sign::to_hex(&sign::key_id(&key.verifying_key())))))
+            }
+            PngCommand::Verify { filename, key_filename } => {
+                let png = Png::load(filename)?;
+                let key = sign::verifying_key_from_hex(&std::fs::read_to_string(key_filename)?)?;
+                let report = sign::verify(&png, &key)?;
+                match report.is_intact() {
+                    true => Ok(PngCmdSuccess::SuccessMsg(report.to_string())),
+                    false => Err(PngError::BadSignature(report.to_string())),
+                }
+            }
+            PngCommand::Watermark { command } => command.run(),
+            PngCommand::Strip { filename, out_filename, keep, privacy, minimal } => {
+                let mut png = Png::load(filename)?;
+                let mut keep_types = Vec::<ChunkType>::new();
+                for k in keep {
+                    keep_types.push(ChunkType::from_str(k)?);
+                }
+
+                let removed = png.strip_ancillary(|ct| {
+                    keep_types.contains(ct) ||
+                        if *privacy {
+                            !PRIVACY_STRIP_TYPES.iter().any(|&t| ct == t) && ct.is_public()
+                        }
+                        else if *minimal {
+                            false
+                        }
+                        else {
+                            RENDERING_TYPES.iter().any(|&t| ct == t)
+                        }
+                });
+
+                let out_f = match out_filename {
+                    None => filename,
+                    Some(out) => out,
+                };
+                png.save(out_f)?;
+
+                let total: usize = removed.iter().map(|c| c.length() as usize + 12).sum();
+                let mut report = format!("Removed {} chunks ({total} bytes)", removed.len());
+                for c in &removed {
+                    report.push_str(&format!("\n  {}: {} bytes", c.chunk_type(), c.length() as usize + 12));
+                }
+                Ok(PngCmdSuccess::SuccessMsg(report))
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Describe an embedded payload and any chunks dropped by the copy policy.
+fn embed_report(len: usize, dropped: &[Chunk]) -> String {
+    let mut report = format!("Embedded {len} bytes in image data.");
+    append_dropped(&mut report, dropped);
+    report
+}
+
+/// List the chunks dropped by the copy policy after a report.
+fn append_dropped(report: &mut String, dropped: &[Chunk]) {
+    for c in dropped {
+        report.push_str(&format!("\nDropped unsafe-to-copy chunk {}", c.chunk_type()));
+    }
+}
+
+/// Decompress a recovered payload and present it as a string if possible, otherwise as bytes.
+fn payload_result(payload: Vec<u8>) -> Result<PngCmdSuccess, PngError> {
+    match String::from_utf8(payload::unpack(&payload)?) {
+        Ok(s) => Ok(PngCmdSuccess::SuccessMsg(s)),
+        Err(e) => Ok(PngCmdSuccess::SuccessBytes(e.into_bytes())),
+    }
+}
+
+/// Expand an option given zero, one or `n` times into one value per frame.
+fn per_frame<T: Clone>(values: &[T], n: usize, default: T) -> Result<Vec<T>, PngError> {
+    match values.len() {
+        0 => Ok(vec![default; n]),
+        1 => Ok(vec![values[0].clone(); n]),
+        len if len == n => Ok(values.to_vec()),
+        len => Err(PngError::BadAnimation(format!("{len} values given for {n} frames"))),
+    }
+}
+
+/// Ancillary chunk types that affect how an image is rendered; kept by default when stripping.
+const RENDERING_TYPES: [&str; 6] = ["tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "sBIT"];
+
+/// Ancillary chunk types that make up an animation; kept when optimizing strips metadata.
+const ANIMATION_TYPES: [&str; 3] = [ACTL, FCTL, FDAT];
+
+/// Ancillary chunk types that may carry identifying information; dropped by the privacy preset.
+const PRIVACY_STRIP_TYPES: [&str; 5] = ["tEXt", "zTXt", "iTXt", "eXIf", "tIME"];
\ No newline at end of file
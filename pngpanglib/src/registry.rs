@@ -0,0 +1,320 @@
+//! A registry of known chunk types.
+//!
+//! Covers the chunks of the PNG specification, the registered extensions and well-known private
+//! chunks written by popular software. Each entry records where the chunk is defined, how many may
+//! appear and where in the file they belong, which [check_order] uses to validate a PNG.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::chunk::ChunkType;
+use crate::png::{Container, Png};
+
+/// Who defines a chunk type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The PNG specification.
+    Specification,
+    /// The registered PNG extensions.
+    Extension,
+    /// A private chunk of the named software.
+    Vendor(&'static str),
+}
+
+/// How many chunks of a type may appear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplicity {
+    /// At most one.
+    Once,
+    /// Any number.
+    Multiple,
+    /// Any number, all next to each other.
+    Consecutive,
+}
+
+/// Where chunks of a type belong in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// First chunk of the file.
+    First,
+    /// Last chunk of the file.
+    Last,
+    /// Before PLTE and the image data.
+    BeforePlte,
+    /// After PLTE and before the image data.
+    AfterPlte,
+    /// Before the image data.
+    BeforeIdat,
+    /// After the image data starts.
+    AfterIdat,
+    /// No constraint.
+    Anywhere,
+}
+
+/// What is known about a chunk type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// The type code.
+    pub chunk_type: &'static str,
+    /// Short friendly name.
+    pub name: &'static str,
+    /// What the chunk holds.
+    pub description: &'static str,
+    /// Where the chunk is defined.
+    pub reference: &'static str,
+    /// Who defines the chunk.
+    pub source: Source,
+    /// How many chunks of the type may appear.
+    pub multiplicity: Multiplicity,
+    /// Where the chunks belong.
+    pub placement: Placement,
+}
+
+impl ChunkInfo {
+    /// Test if the chunk type is flagged critical.
+    pub fn is_critical(&self) -> bool {
+        self.chunk_type.as_bytes()[0].is_ascii_uppercase()
+    }
+
+    /// Test if the chunk type is defined by the specification or its registered extensions.
+    pub fn is_registered(&self) -> bool {
+        !matches!(self.source, Source::Vendor(_))
+    }
+}
+
+const fn info(chunk_type: &'static str, name: &'static str, description: &'static str, reference: &'static str,
+              source: Source, multiplicity: Multiplicity, placement: Placement) -> ChunkInfo {
+    ChunkInfo { chunk_type, name, description, reference, source, multiplicity, placement }
+}
+
+use Multiplicity::*;
+use Placement::*;
+use Source::*;
+
+/// Every chunk type known to this library.
+pub static KNOWN_CHUNKS: [ChunkInfo; 45] = [
+    info("IHDR", "Image header", "Width, height, bit depth, color type and interlacing.",
+         "PNG 1.2 §4.1.1", Specification, Once, First),
+    info("PLTE", "Palette", "Palette entries of an indexed image.",
+         "PNG 1.2 §4.1.2", Specification, Once, BeforeIdat),
+    info("IDAT", "Image data", "The zlib-compressed, filtered scanlines.",
+         "PNG 1.2 §4.1.3", Specification, Consecutive, Anywhere),
+    info("IEND", "Image trailer", "Marks the end of the datastream.",
+         "PNG 1.2 §4.1.4", Specification, Once, Last),
+    info("tRNS", "Transparency", "Alpha for palette entries, or a transparent color.",
+         "PNG 1.2 §4.2.1.1", Specification, Once, AfterPlte),
+    info("gAMA", "Image gamma", "Gamma of the encoding.",
+         "PNG 1.2 §4.2.2.1", Specification, Once, BeforePlte),
+    info("cHRM", "Primary chromaticities", "Chromaticities of the primaries and white point.",
+         "PNG 1.2 §4.2.2.2", Specification, Once, BeforePlte),
+    info("sRGB", "Standard RGB color space", "The image uses sRGB with the given rendering intent.",
+         "PNG 1.2 §4.2.2.3", Specification, Once, BeforePlte),
+    info("iCCP", "Embedded ICC profile", "A compressed ICC color profile.",
+         "PNG 1.2 §4.2.2.4", Specification, Once, BeforePlte),
+    info("tEXt", "Textual data", "A Latin-1 keyword and text.",
+         "PNG 1.2 §4.2.3.1", Specification, Multiple, Anywhere),
+    info("zTXt", "Compressed textual data", "A Latin-1 keyword and compressed text.",
+         "PNG 1.2 §4.2.3.2", Specification, Multiple, Anywhere),
+    info("iTXt", "International textual data", "A keyword and UTF-8 text, optionally compressed and translated.",
+         "PNG 1.2 §4.2.3.3", Specification, Multiple, Anywhere),
+    info("bKGD", "Background color", "Default background to display the image against.",
+         "PNG 1.2 §4.2.4.1", Specification, Once, AfterPlte),
+    info("pHYs", "Physical pixel dimensions", "Pixel size or aspect ratio.",
+         "PNG 1.2 §4.2.4.2", Specification, Once, BeforeIdat),
+    info("sBIT", "Significant bits", "Number of significant bits in each channel of the source.",
+         "PNG 1.2 §4.2.4.3", Specification, Once, BeforePlte),
+    info("sPLT", "Suggested palette", "A palette for displays with few colors.",
+         "PNG 1.2 §4.2.4.4", Specification, Multiple, BeforeIdat),
+    info("hIST", "Palette histogram", "Approximate usage frequency of each palette entry.",
+         "PNG 1.2 §4.2.4.5", Specification, Once, AfterPlte),
+    info("tIME", "Last modification time", "When the image was last changed.",
+         "PNG 1.2 §4.2.4.6", Specification, Once, Anywhere),
+    info("cICP", "Coding-independent code points", "Color primaries, transfer function and matrix coefficients.",
+         "PNG 3rd edition", Specification, Once, BeforePlte),
+    info("mDCV", "Mastering display color volume", "Color volume of the display the image was mastered on.",
+         "PNG 3rd edition", Specification, Once, BeforeIdat),
+    info("cLLI", "Content light level", "Maximum content and frame-average light levels.",
+         "PNG 3rd edition", Specification, Once, BeforeIdat),
+    info("eXIf", "Exif metadata", "An Exif profile.",
+         "PNG 3rd edition", Specification, Once, BeforeIdat),
+    info("acTL", "Animation control", "Number of frames and plays of an animated PNG.",
+         "PNG 3rd edition", Specification, Once, BeforeIdat),
+    info("fcTL", "Frame control", "Region, delay, dispose and blend operations of a frame.",
+         "PNG 3rd edition", Specification, Multiple, Anywhere),
+    info("fdAT", "Frame data", "Image data of a frame after the first.",
+         "PNG 3rd edition", Specification, Multiple, AfterIdat),
+    info("oFFs", "Image offset", "Position of the image on a page.",
+         "PNG extensions 1.5.0", Extension, Once, BeforeIdat),
+    info("pCAL", "Pixel calibration", "Mapping of sample values to physical quantities.",
+         "PNG extensions 1.5.0", Extension, Once, BeforeIdat),
+    info("sCAL", "Physical scale", "Physical size of the subject.",
+         "PNG extensions 1.5.0", Extension, Once, BeforeIdat),
+    info("gIFg", "GIF graphic control", "Disposal method, input flag and delay from a GIF.",
+         "PNG extensions 1.5.0", Extension, Multiple, Anywhere),
+    info("gIFx", "GIF application extension", "An application extension block from a GIF.",
+         "PNG extensions 1.5.0", Extension, Multiple, Anywhere),
+    info("gIFt", "GIF plain text", "A plain text extension from a GIF; deprecated.",
+         "PNG extensions 1.5.0", Extension, Multiple, Anywhere),
+    info("sTER", "Stereo image", "The image is a side-by-side stereo pair.",
+         "PNG extensions 1.5.0", Extension, Once, BeforeIdat),
+    info("dSIG", "Digital signature", "A digital signature over the datastream.",
+         "PNG extensions 1.5.0", Extension, Multiple, Anywhere),
+    info("iDOT", "Apple parallel decoding", "Offsets that let Apple decoders split the image data between threads.",
+         "Apple", Vendor("Apple"), Once, BeforeIdat),
+    info("CgBI", "Apple iOS optimization", "Marks an iOS-optimized file with premultiplied BGRA and raw deflate.",
+         "Apple", Vendor("Apple"), Once, Anywhere),
+    info("vpAg", "Virtual page", "Size of the virtual canvas the image sits on.",
+         "ImageMagick", Vendor("ImageMagick"), Once, BeforeIdat),
+    info("caNv", "Canvas", "Size of the canvas and offset of the image on it.",
+         "ImageMagick", Vendor("ImageMagick"), Once, BeforeIdat),
+    info("orNT", "Orientation", "Exif-style orientation of the image.",
+         "ImageMagick", Vendor("ImageMagick"), Once, BeforeIdat),
+    info("prVW", "Fireworks preview", "A preview image of the Fireworks document.",
+         "Adobe Fireworks", Vendor("Adobe Fireworks"), Once, Anywhere),
+    info("mkBF", "Fireworks document", "Editable Fireworks document data.",
+         "Adobe Fireworks", Vendor("Adobe Fireworks"), Multiple, Anywhere),
+    info("mkBS", "Fireworks document", "Editable Fireworks document data.",
+         "Adobe Fireworks", Vendor("Adobe Fireworks"), Multiple, Anywhere),
+    info("mkBT", "Fireworks document", "Editable Fireworks document data.",
+         "Adobe Fireworks", Vendor("Adobe Fireworks"), Multiple, Anywhere),
+    info("mkTS", "Fireworks document", "Editable Fireworks document data.",
+         "Adobe Fireworks", Vendor("Adobe Fireworks"), Multiple, Anywhere),
+    info("npTc", "Nine-patch", "Stretchable regions and padding of an Android nine-patch image.",
+         "Android", Vendor("Android"), Once, Anywhere),
+    info("siGn", "PNG PANG BAM signature", "An Ed25519 signature over selected chunks.",
+         "pngpanglib::sign", Vendor("PNG PANG BAM"), Once, Anywhere),
+];
+
+/// Look up what is known about a chunk type.
+pub fn lookup(chunk_type: &ChunkType) -> Option<&'static ChunkInfo> {
+    static BY_TYPE: OnceLock<HashMap<[u8; 4], &'static ChunkInfo>> = OnceLock::new();
+    let by_type = BY_TYPE.get_or_init(|| KNOWN_CHUNKS.iter()
+        .map(|i| (ChunkType::from_str(i.chunk_type).expect("registry type codes are valid").bytes(), i))
+        .collect());
+    by_type.get(&chunk_type.bytes()).copied()
+}
+
+/// Get a friendly name for a chunk type, or say what kind of unknown chunk it is.
+pub fn friendly_name(chunk_type: &ChunkType) -> String {
+    match lookup(chunk_type) {
+        Some(info) => info.name.to_string(),
+        None => format!("unknown {} {} chunk",
+                        if chunk_type.is_public() { "public" } else { "private" },
+                        if chunk_type.is_critical() { "critical" } else { "ancillary" }),
+    }
+}
+
+/// A chunk found where the registry says it does not belong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderProblem {
+    /// Position of the chunk in the file, counting from 0.
+    pub index: usize,
+    /// Type of the chunk.
+    pub chunk_type: String,
+    /// What is wrong.
+    pub problem: String,
+}
+
+impl Display for OrderProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk {} ({}) {}", self.index, self.chunk_type, self.problem)
+    }
+}
+
+/// Check the order and multiplicity of the known chunks of a PNG against the registry.
+/// MNG and JNG datastreams follow other rules and are not checked.
+pub fn check_order(png: &Png) -> Vec<OrderProblem> {
+    let mut problems = Vec::new();
+    if png.container() != Container::Png {
+        return problems;
+    }
+
+    let chunks = png.chunks();
+    let first_plte = chunks.iter().position(|c| *c.chunk_type() == *"PLTE");
+    let first_idat = chunks.iter().position(|c| *c.chunk_type() == *"IDAT");
+    let mut counts = HashMap::<&'static str, usize>::new();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let Some(info) = lookup(chunk.chunk_type()) else {
+            continue;
+        };
+        let mut report = |problem: &str| problems.push(OrderProblem {
+            index,
+            chunk_type: info.chunk_type.to_string(),
+            problem: problem.to_string(),
+        });
+
+        let count = counts.entry(info.chunk_type).or_insert(0);
+        *count += 1;
+        match info.multiplicity {
+            Once if *count > 1 => report("repeats a chunk that may appear only once"),
+            Consecutive if *count > 1 && chunks[index - 1].chunk_type() != chunk.chunk_type() => {
+                report("is separated from the previous chunk of its type")
+            },
+            _ => {},
+        }
+
+        let before = |pos: Option<usize>| pos.is_some_and(|p| index > p);
+        let after = |pos: Option<usize>| pos.is_some_and(|p| index < p);
+        match info.placement {
+            First if index != 0 => report("must be the first chunk"),
+            Last if index != chunks.len() - 1 => report("must be the last chunk"),
+            BeforePlte if before(first_plte) => report("must come before PLTE"),
+            AfterPlte if after(first_plte) => report("must come after PLTE"),
+            AfterIdat if first_idat.is_none_or(|p| index < p) => report("must come after IDAT"),
+            _ => {},
+        }
+        if matches!(info.placement, BeforePlte | AfterPlte | BeforeIdat) && before(first_idat) {
+            report("must come before IDAT");
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    fn png_of(types: &[&str]) -> Png {
+        Png::from_chunks(types.iter()
+            .map(|t| Chunk::new(&ChunkType::from_str(t).unwrap(), Vec::new()))
+            .collect())
+    }
+
+    #[test]
+    fn test_registry_is_consistent() {
+        for (i, info) in KNOWN_CHUNKS.iter().enumerate() {
+            let ct = ChunkType::from_str(info.chunk_type).unwrap();
+            assert!(ct.is_valid(), "{}", info.chunk_type);
+            assert_eq!(ct.is_critical(), info.is_critical());
+            assert!(KNOWN_CHUNKS[..i].iter().all(|other| other.chunk_type != info.chunk_type), "{}", info.chunk_type);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup(&ChunkType::from_str("vpAg").unwrap()).unwrap().source, Vendor("ImageMagick"));
+        assert_eq!(friendly_name(&ChunkType::from_str("tEXt").unwrap()), "Textual data");
+        assert_eq!(friendly_name(&ChunkType::from_str("ruSt").unwrap()), "unknown private ancillary chunk");
+    }
+
+    #[test]
+    fn test_check_order() {
+        assert!(check_order(&png_of(&["IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND"])).is_empty());
+
+        let problems: Vec<String> = check_order(&png_of(&["IHDR", "tRNS", "PLTE", "IDAT", "gAMA", "tEXt", "IDAT", "gAMA", "IEND"]))
+            .iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems, vec![
+            "chunk 1 (tRNS) must come after PLTE",
+            "chunk 4 (gAMA) must come before PLTE",
+            "chunk 4 (gAMA) must come before IDAT",
+            "chunk 6 (IDAT) is separated from the previous chunk of its type",
+            "chunk 7 (gAMA) repeats a chunk that may appear only once",
+            "chunk 7 (gAMA) must come before PLTE",
+            "chunk 7 (gAMA) must come before IDAT",
+        ]);
+    }
+}
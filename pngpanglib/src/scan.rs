@@ -0,0 +1,537 @@
+//! Detection of content that may hide a payload.
+//!
+//! Each check produces [Finding]s with a score from 0 to 100 expressing how suspicious the
+//! content is. The statistical checks on pixel data are hints only: a clean image can score
+//! above zero and a lightly embedded one can score low.
+
+use std::fmt::{Display, Formatter};
+
+use crate::chunk::Chunk;
+use crate::image::{filtered_len, inflate, zlib_stream_len, ImageData};
+use crate::png::{Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Ancillary chunks with more data than this are reported as oversized.
+const OVERSIZED_CHUNK: usize = 64 * 1024;
+/// Text shorter than this is too short for a meaningful entropy estimate.
+const MIN_ENTROPY_LEN: usize = 32;
+/// RS estimates below this embedding rate are within the noise of clean images.
+const RS_THRESHOLD: f64 = 0.15;
+/// Text above this many bits per byte is reported as high-entropy. English prose is about 4.2.
+const HIGH_ENTROPY: f64 = 5.0;
+/// Compressed text is decompressed up to this many bytes; longer text is reported, not kept.
+const MAX_TEXT_LEN: usize = 1024 * 1024;
+/// Image data is decompressed up to this many bytes past the scanlines; more is reported, not kept.
+const MAX_STREAM_EXCESS: usize = 1024 * 1024;
+
+/// The kind of suspicious content found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    /// A private chunk not defined by any specification.
+    PrivateChunk,
+    /// A public chunk type not known to this library.
+    UnknownChunk,
+    /// A chunk type with bytes other than ASCII letters or the reserved bit set.
+    InvalidChunkType,
+    /// Bytes following the end chunk.
+    TrailingData,
+    /// An ancillary chunk with an unusually large amount of data.
+    OversizedChunk,
+    /// A text chunk whose content looks random or encoded.
+    HighEntropyText,
+    /// A compressed text chunk that expands beyond the size scanned.
+    OversizedText,
+    /// Bytes in the IDAT chunks after the end of the zlib stream.
+    ImageStreamTrailer,
+    /// Decompressed image data beyond the scanlines described by the header.
+    ImageStreamExcess,
+    /// Sample histograms show the even/odd pairing typical of LSB embedding.
+    ChiSquare,
+    /// RS analysis estimates that sample LSBs have been replaced.
+    RsAnalysis,
+}
+
+/// A single suspicious item.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// What was found.
+    pub kind: FindingKind,
+    /// How suspicious it is, from 0 to 100.
+    pub score: u8,
+    /// Index of the chunk concerned, if any.
+    pub chunk_index: Option<usize>,
+    /// Human-readable details.
+    pub description: String,
+}
+
+/// All findings of a scan, most suspicious first.
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    /// The findings.
+    pub findings: Vec<Finding>,
+}
+
+impl ScanReport {
+    /// Get the highest score of any finding, or 0 if there are none.
+    pub fn score(&self) -> u8 {
+        self.findings.iter().map(|f| f.score).max().unwrap_or(0)
+    }
+
+    fn add(&mut self, kind: FindingKind, score: f64, chunk_index: Option<usize>, description: String) {
+        self.findings.push(Finding {
+            kind,
+            score: score.clamp(0.0, 100.0).round() as u8,
+            chunk_index,
+            description,
+        });
+    }
+}
+
+impl Display for ScanReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.findings.is_empty() {
+            return write!(f, "No findings.");
+        }
+        write!(f, "Overall score: {}", self.score())?;
+        for finding in &self.findings {
+            write!(f, "\n  [{:>3}] {:?}: {}", finding.score, finding.kind, finding.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scan a PNG file given as bytes.
+pub fn scan_bytes(bytes: &[u8]) -> Result<ScanReport> {
+    Ok(scan(&Png::try_from(bytes)?))
+}
+
+/// Scan a PNG for suspicious chunks, trailing data and pixel statistics.
+pub fn scan(png: &Png) -> ScanReport {
+    let mut report = ScanReport::default();
+
+    let trailing = png.trailing_data().len();
+    if trailing > 0 {
+        report.add(FindingKind::TrailingData, 80.0, None,
+                   format!("{trailing} bytes after {}", png.container().end_chunk_type()));
+    }
+
+    for (i, c) in png.chunks().iter().enumerate() {
+        let ct = c.chunk_type();
+        if !ct.is_valid() {
+            report.add(FindingKind::InvalidChunkType, 70.0, Some(i),
+                       format!("invalid chunk type \"{}\" with {} bytes", ct.bytes().escape_ascii(), c.length()));
+        }
+        else if ct.is_standard() {
+            // registered chunk, nothing to report
+        }
+        else if !ct.is_public() {
+            report.add(FindingKind::PrivateChunk, 60.0, Some(i),
+                       format!("private chunk {ct} with {} bytes", c.length()));
+        }
+        else {
+            report.add(FindingKind::UnknownChunk, 30.0, Some(i),
+                       format!("unknown public chunk {ct} with {} bytes", c.length()));
+        }
+
+        if !ct.is_critical() && c.length() as usize > OVERSIZED_CHUNK {
+            report.add(FindingKind::OversizedChunk, 40.0, Some(i),
+                       format!("{ct} chunk with {} bytes", c.length()));
+        }
+
+        if let Some(text) = text_content(c) {
+            if text.len() > MAX_TEXT_LEN {
+                report.add(FindingKind::OversizedText, 60.0, Some(i),
+                           format!("{ct} text expands to more than {MAX_TEXT_LEN} bytes"));
+                continue;
+            }
+            let entropy = entropy(&text);
+            if text.len() >= MIN_ENTROPY_LEN && entropy > HIGH_ENTROPY {
+                report.add(FindingKind::HighEntropyText, 30.0 + (entropy - HIGH_ENTROPY) * 20.0, Some(i),
+                           format!("{ct} text of {} bytes with {entropy:.2} bits of entropy per byte", text.len()));
+            }
+        }
+    }
+
+    if png.container() == crate::png::Container::Png {
+        let stream = png.image_stream();
+        if let Ok(len) = zlib_stream_len(&stream) {
+            if len < stream.len() {
+                report.add(FindingKind::ImageStreamTrailer, 80.0, None,
+                           format!("{} bytes after the end of the IDAT zlib stream", stream.len() - len));
+            }
+        }
+        if let Ok(needed) = png.image_header().and_then(|h| filtered_len(&h)) {
+            match inflate(&stream, needed.saturating_add(MAX_STREAM_EXCESS)).map(|f| f.len().saturating_sub(needed)) {
+                Ok(0) | Err(_) => {},
+                Ok(excess) if excess > MAX_STREAM_EXCESS => {
+                    report.add(FindingKind::ImageStreamExcess, 80.0, None,
+                               format!("more than {MAX_STREAM_EXCESS} bytes of decompressed data after the image"));
+                }
+                Ok(excess) => {
+                    report.add(FindingKind::ImageStreamExcess, 80.0, None,
+                               format!("{excess} bytes of decompressed data after the image"));
+                }
+            }
+        }
+
+        if let Ok(image) = png.decode_image() {
+            scan_samples(&image, &mut report);
+        }
+    }
+
+    sort_findings(&mut report);
+    report
+}
+
+fn sort_findings(report: &mut ScanReport) {
+    report.findings.sort_by_key(|f| std::cmp::Reverse(f.score));
+}
+
+/// Get the text of a tEXt, zTXt or iTXt chunk, decompressing it if needed.
+/// Compressed text is cut one byte past [MAX_TEXT_LEN], so longer text can be detected.
+pub(crate) fn text_content(chunk: &Chunk) -> Option<Vec<u8>> {
+    let data = chunk.data();
+    let keyword_end = data.iter().position(|&b| b == 0)?;
+    let rest = &data[keyword_end + 1..];
+
+    if chunk.chunk_type() == "tEXt" {
+        Some(rest.to_vec())
+    }
+    else if chunk.chunk_type() == "zTXt" {
+        inflate(rest.get(1..)?, MAX_TEXT_LEN).ok()
+    }
+    else if chunk.chunk_type() == "iTXt" {
+        let compressed = *rest.first()? == 1;
+        let rest = rest.get(2..)?;
+        let lang_end = rest.iter().position(|&b| b == 0)?;
+        let rest = &rest[lang_end + 1..];
+        let translated_end = rest.iter().position(|&b| b == 0)?;
+        let text = &rest[translated_end + 1..];
+        if compressed { inflate(text, MAX_TEXT_LEN).ok() } else { Some(text.to_vec()) }
+    }
+    else {
+        None
+    }
+}
+
+/// Shannon entropy in bits per byte.
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    data.iter().for_each(|&b| counts[b as usize] += 1);
+    let n = data.len() as f64;
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / n;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Split the color samples of an image into one vector per channel, in raster order.
+/// For 16 bit images, the low byte of each sample is used. Alpha and indexed images are skipped.
+fn color_channels(image: &ImageData) -> Vec<Vec<u8>> {
+    let header = image.header();
+    let color_count = match header.color_type {
+        0 | 4 => 1,
+        2 | 6 => 3,
+        _ => return Vec::new(),
+    };
+    let sample_bytes = header.bit_depth as usize / 8;
+    let (Ok(channels), true) = (header.channels(), sample_bytes > 0) else {
+        return Vec::new();
+    };
+
+    let pixel_bytes = channels * sample_bytes;
+    (0..color_count)
+        .map(|ch| image.data().chunks_exact(pixel_bytes)
+            .map(|px| px[ch * sample_bytes + sample_bytes - 1])
+            .collect())
+        .collect()
+}
+
+fn scan_samples(image: &ImageData, report: &mut ScanReport) {
+    let channels = color_channels(image);
+    if channels.is_empty() {
+        return;
+    }
+
+    let p = channels.iter().map(|c| chi_square_p(c)).fold(0.0f64, f64::max);
+    if p > 0.9 {
+        report.add(FindingKind::ChiSquare, p * 80.0, None,
+                   format!("sample value pairs are unusually balanced (p = {p:.3})"));
+    }
+
+    let rate = channels.iter().map(|c| rs_estimate(c, image.header().width as usize)).fold(0.0f64, f64::max);
+    if rate > RS_THRESHOLD {
+        report.add(FindingKind::RsAnalysis, rate * 100.0, None,
+                   format!("estimated {:.0}% of sample LSBs replaced", rate * 100.0));
+    }
+}
+
+/// Chi-square attack of Westfeld and Pfitzmann.
+/// Returns the probability that the pairs of values (2k, 2k+1) were equalized by LSB embedding.
+fn chi_square_p(samples: &[u8]) -> f64 {
+    let mut histogram = [0f64; 256];
+    samples.iter().for_each(|&s| histogram[s as usize] += 1.0);
+
+    let mut chi = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) / 2.0;
+        if expected > 5.0 {
+            chi += (pair[0] - expected).powi(2) / expected;
+            categories += 1;
+        }
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    1.0 - regularized_gamma_p((categories - 1) as f64 / 2.0, chi / 2.0)
+}
+
+/// RS analysis of Fridrich, Goljan and Du. Estimates the fraction of samples with replaced LSBs.
+/// Samples are grouped by four along each row of `width` pixels.
+fn rs_estimate(samples: &[u8], width: usize) -> f64 {
+    let flipped: Vec<u8> = samples.iter().map(|&s| s ^ 1).collect();
+    let (rm, sm, rn, sn) = rs_counts(samples, width);
+    let (rm1, sm1, rn1, sn1) = rs_counts(&flipped, width);
+
+    let d0 = rm - sm;
+    let d1 = rm1 - sm1;
+    let dn0 = rn - sn;
+    let dn1 = rn1 - sn1;
+
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+    let z = if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 { return 0.0; }
+        -c / b
+    }
+    else {
+        let disc = b * b - 4.0 * a * c;
+        if disc < 0.0 {
+            return 0.0;
+        }
+        let r1 = (-b + disc.sqrt()) / (2.0 * a);
+        let r2 = (-b - disc.sqrt()) / (2.0 * a);
+        if r1.abs() < r2.abs() { r1 } else { r2 }
+    };
+    let p = z / (z - 0.5);
+    if p.is_finite() { p.clamp(0.0, 1.0) } else { 0.0 }
+}
+
+/// Get the fractions of regular and singular groups under the mask [0 1 1 0] and its negative.
+fn rs_counts(samples: &[u8], width: usize) -> (f64, f64, f64, f64) {
+    const MASK: [bool; 4] = [false, true, true, false];
+    let smoothness = |g: &[i16; 4]| -> i32 { g.windows(2).map(|w| (w[1] - w[0]).abs() as i32).sum() };
+
+    let (mut rm, mut sm, mut rn, mut sn, mut groups) = (0, 0, 0, 0, 0);
+    for row in samples.chunks(width.max(1)) {
+        for g in row.chunks_exact(4) {
+            let group = [g[0] as i16, g[1] as i16, g[2] as i16, g[3] as i16];
+            let f = smoothness(&group);
+
+            let mut pos = group;
+            let mut neg = group;
+            for i in 0..4 {
+                if MASK[i] {
+                    pos[i] ^= 1;
+                    neg[i] = ((neg[i] + 1) ^ 1) - 1;
+                }
+            }
+            let (fp, fn_) = (smoothness(&pos), smoothness(&neg));
+            if fp > f { rm += 1; } else if fp < f { sm += 1; }
+            if fn_ > f { rn += 1; } else if fn_ < f { sn += 1; }
+            groups += 1;
+        }
+    }
+    if groups == 0 {
+        return (0.0, 0.0, 0.0, 0.0);
+    }
+    let n = groups as f64;
+    (rm as f64 / n, sm as f64 / n, rn as f64 / n, sn as f64 / n)
+}
+
+/// The regularized lower incomplete gamma function P(a, x).
+fn regularized_gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let ln_prefix = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        // series expansion
+        let (mut sum, mut term, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-14 {
+                break;
+            }
+        }
+        (sum.ln() + ln_prefix).exp()
+    }
+    else {
+        // continued fraction for Q(a, x), using the modified Lentz method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny { d = tiny; }
+            c = b + an / c;
+            if c.abs() < tiny { c = tiny; }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-14 {
+                break;
+            }
+        }
+        1.0 - (ln_prefix.exp() * h)
+    }
+}
+
+/// Natural logarithm of the gamma function, using the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091,
+        -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS.iter().enumerate()
+        .fold(1.000000000190015, |acc, (i, c)| acc + c / (x + 1.0 + i as f64));
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::chunk::ChunkType;
+    use crate::image::{deflate, EncodeOptions};
+    use crate::png::ImageHeader;
+    use crate::stego::lsb::{self, LsbConfig};
+    use crate::stego::Prng;
+    use crate::stego::stream::{self, StreamMode};
+
+    fn testing_png() -> Png {
+        let header = ImageHeader {
+            width: 64,
+            height: 64,
+            bit_depth: 8,
+            color_type: 2,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        // smooth gradients with only even values
+        let data: Vec<u8> = (0..64 * 64 * 3)
+            .map(|i| {
+                let (x, y, c) = ((i / 3) % 64, i / 192, i % 3);
+                ((x * 2 + y * (c + 1)) as u8) & !1
+            })
+            .collect();
+        let mut png = Png::from_chunks(vec![header.to_chunk(), Chunk::new(&ChunkType::END_CHUNK, Vec::new())]);
+        png.set_image(&ImageData::new(header, data).unwrap(), &EncodeOptions::default()).unwrap();
+        png
+    }
+
+    fn kinds(report: &ScanReport) -> Vec<FindingKind> {
+        report.findings.iter().map(|f| f.kind).collect()
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let report = scan(&testing_png());
+        assert!(report.findings.is_empty(), "{report}");
+    }
+
+    #[test]
+    fn test_chunk_findings() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(&ChunkType::from_str("ruSt").unwrap(), b"secret".to_vec()));
+        let mut text = b"Comment\0".to_vec();
+        let mut prng = Prng::from_passphrase("entropy");
+        text.extend((0..200).map(|_| prng.next_u64() as u8));
+        png.append_chunk(Chunk::new(&ChunkType::from_str("tEXt").unwrap(), text));
+        png.append_chunk(Chunk::new(&ChunkType::from_str("tEXt").unwrap(), b"Comment\0just a plain old comment, nothing to see".to_vec()));
+
+        png.set_trailing_data(b"after the end".to_vec());
+        let report = scan(&png);
+        assert_eq!(kinds(&report), vec![FindingKind::TrailingData, FindingKind::HighEntropyText, FindingKind::PrivateChunk]);
+        assert_eq!(report.findings[0].description, "13 bytes after IEND");
+        assert_eq!(report.score(), 80);
+    }
+
+    #[test]
+    fn test_invalid_chunk_type() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(&ChunkType::from_raw(*b"ru1t"), b"secret".to_vec()));
+        let report = scan(&Png::try_from(png.as_bytes().as_slice()).unwrap());
+        assert_eq!(kinds(&report), vec![FindingKind::InvalidChunkType]);
+        assert_eq!(report.findings[0].description, "invalid chunk type \"ru1t\" with 6 bytes");
+    }
+
+    #[test]
+    fn test_image_stream_trailer() {
+        let mut png = testing_png();
+        let mut stream = png.image_stream();
+        stream.extend_from_slice(b"hidden");
+        png.replace_chunks(&ChunkType::from_str("IDAT").unwrap(),
+                           vec![Chunk::new(&ChunkType::from_str("IDAT").unwrap(), stream)]);
+        assert_eq!(kinds(&scan(&png)), vec![FindingKind::ImageStreamTrailer]);
+    }
+
+    #[test]
+    fn test_stored_block_payload_detected() {
+        let mut png = testing_png();
+        stream::embed(&mut png, b"hidden", StreamMode::StoredBlock, 6).unwrap();
+        assert_eq!(kinds(&scan(&png)), vec![FindingKind::ImageStreamExcess]);
+    }
+
+    #[test]
+    fn test_decompression_bounded() {
+        let mut png = testing_png();
+        let idat_type = ChunkType::from_str("IDAT").unwrap();
+        let needed = filtered_len(&png.image_header().unwrap()).unwrap();
+        let mut filtered = inflate(&png.image_stream(), needed).unwrap();
+        filtered.resize(needed + 2 * MAX_STREAM_EXCESS, 0);
+        png.replace_chunks(&idat_type, vec![Chunk::new(&idat_type, deflate(&filtered, 9))]);
+        let mut text = b"Comment\0\0".to_vec();
+        text.extend(deflate(&vec![b'a'; 2 * MAX_TEXT_LEN], 9));
+        png.append_chunk(Chunk::new(&ChunkType::from_str("zTXt").unwrap(), text));
+
+        let report = scan(&png);
+        assert_eq!(kinds(&report), vec![FindingKind::ImageStreamExcess, FindingKind::OversizedText]);
+        assert_eq!(report.findings[0].description, "more than 1048576 bytes of decompressed data after the image");
+        assert_eq!(report.findings[1].description, "zTXt text expands to more than 1048576 bytes");
+    }
+
+    #[test]
+    fn test_lsb_embedding_detected() {
+        let mut png = testing_png();
+        let config = LsbConfig::default();
+        let capacity = lsb::image_capacity(&png, &config).unwrap();
+        let mut prng = Prng::from_passphrase("payload");
+        let payload: Vec<u8> = (0..capacity).map(|_| prng.next_u64() as u8).collect();
+        lsb::embed(&mut png, &payload, &config, &EncodeOptions::default()).unwrap();
+
+        let report = scan(&png);
+        assert!(kinds(&report).contains(&FindingKind::ChiSquare), "{report}");
+        assert!(kinds(&report).contains(&FindingKind::RsAnalysis), "{report}");
+    }
+
+    #[test]
+    fn test_regularized_gamma() {
+        assert!((regularized_gamma_p(1.0, 1.0) - (1.0 - (-1.0f64).exp())).abs() < 1e-10);
+        // for integer a, Q(a, x) = e^-x * sum of x^k / k! for k < a
+        let q = (-20.0f64).exp() * (1.0 + 20.0 + 200.0 + 8000.0 / 6.0 + 160000.0 / 24.0);
+        assert!((regularized_gamma_p(5.0, 20.0) - (1.0 - q)).abs() < 1e-10);
+    }
+}
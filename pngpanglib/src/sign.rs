@@ -0,0 +1,346 @@
+//! Ed25519 signatures over selected chunks.
+//!
+//! Signing records a manifest of the covered chunks, each with the SHA-256 digest of its type and
+//! data, and signs the digest of that manifest. Every critical chunk is covered, along with any
+//! ancillary types chosen by the signer. The manifest and signature are stored in a private
+//! [SIGNATURE_CHUNK] so verification can report which covered chunks changed, went missing or
+//! were added after signing.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+use sha2::{Digest, Sha256};
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::diff;
+use crate::png::{Png, PngError};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Chunk type holding the signature: ancillary, private and safe to copy, so editors keep it
+/// and tampering shows up on verification.
+pub const SIGNATURE_CHUNK: &str = "siGn";
+
+const VERSION: u8 = 1;
+const KEY_ID_LEN: usize = 8;
+const DIGEST_LEN: usize = 32;
+const MANIFEST_START: usize = 1 + KEY_ID_LEN + ed25519_dalek::SIGNATURE_LENGTH;
+
+/// Get the identifier of a public key: the first bytes of its SHA-256 digest.
+pub fn key_id(key: &VerifyingKey) -> [u8; KEY_ID_LEN] {
+    let digest = Sha256::digest(key.as_bytes());
+    digest[..KEY_ID_LEN].try_into().unwrap()
+}
+
+/// Generate a new signing key from the operating system's random source.
+pub fn generate_key() -> Result<SigningKey> {
+    let mut seed = [0u8; ed25519_dalek::SECRET_KEY_LENGTH];
+    getrandom::getrandom(&mut seed)
+        .map_err(|e| PngError::IO(std::io::Error::other(e.to_string())))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Read a signing key written as hexadecimal.
+pub fn signing_key_from_hex(s: &str) -> Result<SigningKey> {
+    let bytes = from_hex(s).ok_or_else(|| PngError::BadSignature("key is not hexadecimal".to_string()))?;
+    let seed = bytes.try_into()
+        .map_err(|_| PngError::BadSignature("signing key must be 32 bytes".to_string()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Read a verifying key written as hexadecimal.
+pub fn verifying_key_from_hex(s: &str) -> Result<VerifyingKey> {
+    let bytes = from_hex(s).ok_or_else(|| PngError::BadSignature("key is not hexadecimal".to_string()))?;
+    let bytes: [u8; 32] = bytes.try_into()
+        .map_err(|_| PngError::BadSignature("verifying key must be 32 bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| PngError::BadSignature(e.to_string()))
+}
+
+/// Write bytes as lowercase hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// What happened to a covered chunk since signing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverStatus {
+    /// The chunk is as signed.
+    Unchanged,
+    /// The chunk type or data differ from what was signed.
+    Changed,
+    /// The chunk was signed but is no longer present.
+    Missing,
+    /// A chunk of a covered type was added after signing.
+    Added,
+}
+
+/// A chunk covered by a signature and its state.
+#[derive(Debug, Clone)]
+pub struct CoveredChunk {
+    /// The chunk type, as signed or as found for added chunks.
+    pub chunk_type: String,
+    /// What happened to the chunk since signing.
+    pub status: CoverStatus,
+}
+
+/// The outcome of verifying a signature whose manifest is authentic.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    /// Identifier of the key that made the signature.
+    pub key_id: [u8; KEY_ID_LEN],
+    /// The covered chunks in order.
+    pub chunks: Vec<CoveredChunk>,
+}
+
+impl VerifyReport {
+    /// Check whether every covered chunk is unchanged.
+    pub fn is_intact(&self) -> bool {
+        self.chunks.iter().all(|c| c.status == CoverStatus::Unchanged)
+    }
+}
+
+impl Display for VerifyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let changed = self.chunks.iter().filter(|c| c.status != CoverStatus::Unchanged).count();
+        if changed == 0 {
+            write!(f, "Signature by key {} is valid; all {} covered chunks are intact.",
+                   to_hex(&self.key_id), self.chunks.len())?;
+        }
+        else {
+            write!(f, "Signature by key {} is valid, but {changed} of {} covered chunks were tampered with.",
+                   to_hex(&self.key_id), self.chunks.len())?;
+        }
+        for (i, c) in self.chunks.iter().enumerate() {
+            write!(f, "\n  {i:>3} {} {:?}", c.chunk_type, c.status)?;
+        }
+        Ok(())
+    }
+}
+
+/// Select the chunks covered by a signature, in file order.
+fn covered<'a>(png: &'a Png, include: &[ChunkType]) -> Vec<&'a Chunk> {
+    png.chunks().iter()
+        .filter(|c| *c.chunk_type() != *SIGNATURE_CHUNK)
+        .filter(|c| c.chunk_type().is_critical() || include.contains(c.chunk_type()))
+        .collect()
+}
+
+fn chunk_digest(chunk: &Chunk) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(chunk.chunk_type().bytes());
+    hasher.update(chunk.data());
+    hasher.finalize().into()
+}
+
+/// Sign the critical chunks and the chunks of the included ancillary types, replacing any
+/// existing signature. Returns the number of chunks covered.
+pub fn sign(png: &mut Png, key: &SigningKey, include: &[ChunkType]) -> Result<usize> {
+    let chunks = covered(png, include);
+    let type_count = u8::try_from(include.len())
+        .map_err(|_| PngError::Unsupported(format!("signing more than 255 included chunk types ({})", include.len())))?;
+    let mut manifest = vec![type_count];
+    include.iter().for_each(|ct| manifest.extend_from_slice(&ct.bytes()));
+    manifest.extend_from_slice(&(chunks.len() as u32).to_be_bytes());
+    for c in &chunks {
+        manifest.extend_from_slice(&c.chunk_type().bytes());
+        manifest.extend_from_slice(&chunk_digest(c));
+    }
+    let count = chunks.len();
+
+    let signature = key.sign(&Sha256::digest(&manifest));
+    let mut data = vec![VERSION];
+    data.extend_from_slice(&key_id(&key.verifying_key()));
+    data.extend_from_slice(&signature.to_bytes());
+    data.append(&mut manifest);
+
+    let sig_type = ChunkType::from_str(SIGNATURE_CHUNK).unwrap();
+    png.replace_chunks(&sig_type, vec![Chunk::new(&sig_type, data)]);
+    Ok(count)
+}
+
+/// Verify the signature of a PNG and compare the covered chunks against the signed manifest.
+/// Fails if there is no signature, it was made by another key or the manifest was altered.
+pub fn verify(png: &Png, key: &VerifyingKey) -> Result<VerifyReport> {
+    let sig_type = ChunkType::from_str(SIGNATURE_CHUNK).unwrap();
+    let data = png.chunk_by_type(&sig_type).ok_or_else(|| PngError::ChunkNotFound(sig_type.clone()))?.data();
+    let malformed = || PngError::MalformedChunk(SIGNATURE_CHUNK.to_string());
+    if data.len() < MANIFEST_START + 1 || data[0] != VERSION {
+        return Err(malformed());
+    }
+
+    let signed_id: [u8; KEY_ID_LEN] = data[1..1 + KEY_ID_LEN].try_into().unwrap();
+    if signed_id != key_id(key) {
+        return Err(PngError::BadSignature(format!("signed by key {}, not {}",
+                                                  to_hex(&signed_id), to_hex(&key_id(key)))));
+    }
+    let signature = Signature::from_slice(&data[1 + KEY_ID_LEN..MANIFEST_START]).map_err(|_| malformed())?;
+    let manifest = &data[MANIFEST_START..];
+    key.verify(&Sha256::digest(manifest), &signature)
+        .map_err(|_| PngError::BadSignature("signature does not match the signed manifest".to_string()))?;
+
+    // The manifest is authentic from here on
+    let type_count = manifest[0] as usize;
+    let types_end = 1 + type_count * 4;
+    let include: Vec<ChunkType> = manifest.get(1..types_end).ok_or_else(malformed)?
+        .chunks_exact(4)
+        .map(|b| ChunkType::try_from(<[u8; 4]>::try_from(b).unwrap()).map_err(|_| malformed()))
+        .collect::<Result<_>>()?;
+    let count_bytes = manifest.get(types_end..types_end + 4).ok_or_else(malformed)?;
+    let count = u32::from_be_bytes(count_bytes.try_into().unwrap()) as usize;
+    let entries = &manifest[types_end + 4..];
+    if entries.len() != count * (4 + DIGEST_LEN) {
+        return Err(malformed());
+    }
+
+    let signed: Vec<([u8; 4], [u8; DIGEST_LEN])> = entries.chunks_exact(4 + DIGEST_LEN)
+        .map(|e| (e[..4].try_into().unwrap(), e[4..].try_into().unwrap()))
+        .collect();
+    let current: Vec<([u8; 4], [u8; DIGEST_LEN])> = covered(png, &include).iter()
+        .map(|c| (c.chunk_type().bytes(), chunk_digest(c)))
+        .collect();
+
+    // Aligned as in a chunk diff, so a removed or inserted chunk does not make every later chunk
+    // look changed
+    let name = |t: &[u8; 4]| String::from_utf8_lossy(t).to_string();
+    let chunks = diff::align(&signed, &current).into_iter()
+        .filter_map(|step| match step {
+            (Some(i), Some(j)) if signed[i] == current[j] => Some((&signed[i].0, CoverStatus::Unchanged)),
+            (Some(i), Some(_)) => Some((&signed[i].0, CoverStatus::Changed)),
+            (Some(i), None) => Some((&signed[i].0, CoverStatus::Missing)),
+            (None, Some(j)) => Some((&current[j].0, CoverStatus::Added)),
+            (None, None) => None,
+        })
+        .map(|(t, status)| CoveredChunk { chunk_type: name(t), status })
+        .collect();
+
+    Ok(VerifyReport { key_id: signed_id, chunks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(&ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            Chunk::new(&ChunkType::from_str("tEXt").unwrap(), b"Author\0Someone".to_vec()),
+            Chunk::new(&ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            Chunk::new(&ChunkType::from_str("ruSt").unwrap(), b"unsigned".to_vec()),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ])
+    }
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    fn statuses(report: &VerifyReport) -> Vec<(&str, CoverStatus)> {
+        report.chunks.iter().map(|c| (c.chunk_type.as_str(), c.status)).collect()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let mut png = testing_png();
+        let text = ChunkType::from_str("tEXt").unwrap();
+        assert_eq!(sign(&mut png, &key(), &[text]).unwrap(), 4);
+
+        let report = verify(&png, &key().verifying_key()).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(statuses(&report).iter().map(|s| s.0).collect::<Vec<_>>(), vec!["IHDR", "tEXt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_uncovered_changes_ignored() {
+        let mut png = testing_png();
+        sign(&mut png, &key(), &[]).unwrap();
+        png.remove_chunk(&ChunkType::from_str("ruSt").unwrap()).unwrap();
+        png.remove_chunk(&ChunkType::from_str("tEXt").unwrap()).unwrap();
+        assert!(verify(&png, &key().verifying_key()).unwrap().is_intact());
+    }
+
+    #[test]
+    fn test_tampering_reported() {
+        let mut png = testing_png();
+        let text = ChunkType::from_str("tEXt").unwrap();
+        sign(&mut png, &key(), std::slice::from_ref(&text)).unwrap();
+
+        png.replace_chunks(&text, vec![Chunk::new(&text, b"Author\0Someone else".to_vec())]);
+        png.append_chunk(Chunk::new(&ChunkType::from_str("IDAT").unwrap(), vec![4]));
+
+        let report = verify(&png, &key().verifying_key()).unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(statuses(&report), vec![
+            ("IHDR", CoverStatus::Unchanged),
+            ("tEXt", CoverStatus::Changed),
+            ("IDAT", CoverStatus::Unchanged),
+            ("IDAT", CoverStatus::Added),
+            ("IEND", CoverStatus::Unchanged),
+        ]);
+    }
+
+    #[test]
+    fn test_removed_chunk_reported() {
+        let mut png = testing_png();
+        let text = ChunkType::from_str("tEXt").unwrap();
+        sign(&mut png, &key(), std::slice::from_ref(&text)).unwrap();
+        png.remove_chunk(&text).unwrap();
+
+        let report = verify(&png, &key().verifying_key()).unwrap();
+        assert_eq!(statuses(&report), vec![
+            ("IHDR", CoverStatus::Unchanged),
+            ("tEXt", CoverStatus::Missing),
+            ("IDAT", CoverStatus::Unchanged),
+            ("IEND", CoverStatus::Unchanged),
+        ]);
+    }
+
+    #[test]
+    fn test_many_chunks() {
+        let idat = ChunkType::from_str("IDAT").unwrap();
+        let mut chunks = vec![Chunk::new(&ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0])];
+        chunks.extend((0..5000u32).map(|k| Chunk::new(&idat, k.to_be_bytes().to_vec())));
+        chunks.push(Chunk::new(&ChunkType::END_CHUNK, Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+        sign(&mut png, &key(), &[]).unwrap();
+
+        png.replace_chunks(&idat, (0..5000u32).map(|k| Chunk::new(&idat, (k | 1 << 31).to_be_bytes().to_vec())).collect());
+        let report = verify(&png, &key().verifying_key()).unwrap();
+        assert_eq!(report.chunks.iter().filter(|c| c.status == CoverStatus::Changed).count(), 5000);
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let mut png = testing_png();
+        sign(&mut png, &key(), &[]).unwrap();
+        let other = SigningKey::from_bytes(&[8u8; 32]).verifying_key();
+        assert!(matches!(verify(&png, &other), Err(PngError::BadSignature(_))));
+    }
+
+    #[test]
+    fn test_too_many_types() {
+        let mut png = testing_png();
+        let include: Vec<ChunkType> = (0..256u32)
+            .map(|k| ChunkType::from_raw([b'a' + (k / 26 % 26) as u8, b'a' + (k % 26) as u8, b'X', b'x']))
+            .collect();
+        assert!(matches!(sign(&mut png, &key(), &include), Err(PngError::Unsupported(_))));
+        assert!(sign(&mut png, &key(), &include[..255]).is_ok());
+    }
+
+    #[test]
+    fn test_hex_keys() {
+        let hex = to_hex(key().as_bytes());
+        assert_eq!(signing_key_from_hex(&hex).unwrap().as_bytes(), key().as_bytes());
+        assert!(signing_key_from_hex("abc").is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! Steganographic embedding of payloads in image content rather than in dedicated chunks.
+//!
+//! Payloads hidden in private chunks are easy to spot with a chunk listing. The modes in this
+//! module instead hide payloads inside the image data, so the chunk layout looks ordinary.
+
+pub mod lsb;
+pub mod palette;
+pub mod stream;
+pub mod watermark;
+
+/// A small, deterministic pseudo-random generator (SplitMix64).
+/// The embedding order must be reproducible across versions, so no external generator is used.
+pub(crate) struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    /// Seed the generator from a passphrase using FNV-1a.
+    pub(crate) fn from_passphrase(passphrase: &str) -> Prng {
+        let state = passphrase.bytes()
+            .fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+        Prng { state }
+    }
+
+    /// Get the next 64 random bits.
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Get a value in `0..bound`.
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Get the visiting order of `n` items: sequential without a passphrase, or a
+/// passphrase-seeded permutation.
+pub(crate) fn visit_order(n: usize, passphrase: Option<&str>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..n).collect();
+    if let Some(p) = passphrase {
+        let mut prng = Prng::from_passphrase(p);
+        for i in (1..n).rev() {
+            order.swap(i, prng.below(i + 1));
+        }
+    }
+    order
+}
+
+/// Prefix a payload with its length as a 32-bit big-endian integer.
+pub(crate) fn frame_payload(payload: &[u8]) -> Vec<u8> {
+    let mut framed = (payload.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(payload);
+    framed
+}
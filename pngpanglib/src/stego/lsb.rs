@@ -0,0 +1,249 @@
+//! Hides payloads in the least significant bits of image samples.
+//!
+//! The payload is prefixed with its 32-bit length and written most significant bit first into the
+//! low bits of the selected channels. Pixels are visited in raster order, or in a pseudo-random
+//! order seeded by a passphrase. Only true-color and grayscale images with 8 or 16 bit samples are
+//! supported; for 16 bit samples the low byte is used.
+
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::image::{EncodeOptions, ImageData};
+use crate::png::{ImageHeader, Png, PngError};
+use crate::stego::{frame_payload, visit_order};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Selection of channels that carry payload bits.
+/// For grayscale images, any of red, green or blue selects the gray channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels {
+    /// Use the red channel.
+    pub red: bool,
+    /// Use the green channel.
+    pub green: bool,
+    /// Use the blue channel.
+    pub blue: bool,
+    /// Use the alpha channel.
+    pub alpha: bool,
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels { red: true, green: true, blue: true, alpha: false }
+    }
+}
+
+impl FromStr for Channels {
+    type Err = PngError;
+
+    /// Parse a selection such as "rgb" or "a".
+    fn from_str(s: &str) -> Result<Self> {
+        let mut channels = Channels { red: false, green: false, blue: false, alpha: false };
+        for c in s.chars() {
+            match c {
+                'r' => channels.red = true,
+                'g' => channels.green = true,
+                'b' => channels.blue = true,
+                'a' => channels.alpha = true,
+                _ => return Err(PngError::Unsupported(format!("channel '{c}'"))),
+            }
+        }
+        Ok(channels)
+    }
+}
+
+/// Settings for LSB embedding. Extraction must use the same settings as embedding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsbConfig {
+    /// Number of low bits used in each selected sample, 1 to 8.
+    pub bits_per_channel: u8,
+    /// Channels that carry payload bits.
+    pub channels: Channels,
+    /// Passphrase seeding the pixel visiting order.
+    pub passphrase: Option<String>,
+}
+
+impl Default for LsbConfig {
+    fn default() -> Self {
+        LsbConfig {
+            bits_per_channel: 1,
+            channels: Channels::default(),
+            passphrase: None,
+        }
+    }
+}
+
+/// Get the indices of the selected channels within a pixel.
+fn channel_indices(header: &ImageHeader, config: &LsbConfig) -> Result<Vec<usize>> {
+    if !(1..=8).contains(&config.bits_per_channel) {
+        return Err(PngError::Unsupported(format!("{} bits per channel", config.bits_per_channel)));
+    }
+    if header.bit_depth < 8 || header.color_type == 3 {
+        return Err(PngError::Unsupported("LSB embedding in indexed or sub-byte images".to_string()));
+    }
+
+    let ch = config.channels;
+    let color = ch.red || ch.green || ch.blue;
+    let selected = match header.color_type {
+        0 => vec![color],
+        4 => vec![color, ch.alpha],
+        2 => vec![ch.red, ch.green, ch.blue],
+        6 => vec![ch.red, ch.green, ch.blue, ch.alpha],
+        _ => return Err(PngError::MalformedChunk(ImageHeader::CHUNK_TYPE.to_string())),
+    };
+    let indices: Vec<usize> = selected.iter().enumerate().filter(|(_, &s)| s).map(|(i, _)| i).collect();
+    if indices.is_empty() {
+        return Err(PngError::Unsupported("no channels selected for this color type".to_string()));
+    }
+    Ok(indices)
+}
+
+/// Get the byte offsets of the sample bytes that carry payload bits, in embedding order.
+fn positions(header: &ImageHeader, config: &LsbConfig) -> Result<Vec<usize>> {
+    let indices = channel_indices(header, config)?;
+    let sample_bytes = header.bit_depth as usize / 8;
+    let pixel_bytes = header.channels()? * sample_bytes;
+    let pixels = header.width as usize * header.height as usize;
+
+    Ok(visit_order(pixels, config.passphrase.as_deref()).into_iter()
+        .flat_map(|p| indices.iter().map(move |&i| p * pixel_bytes + i * sample_bytes + sample_bytes - 1))
+        .collect())
+}
+
+/// Calculate how many payload bytes an image with this header can hold.
+pub fn capacity(header: &ImageHeader, config: &LsbConfig) -> Result<usize> {
+    let slots = (header.width as usize * header.height as usize).saturating_mul(channel_indices(header, config)?.len());
+    Ok((slots.saturating_mul(config.bits_per_channel as usize) / 8).saturating_sub(4))
+}
+
+/// Embed a payload into decoded image data.
+pub fn embed_image(image: &mut ImageData, payload: &[u8], config: &LsbConfig) -> Result<()> {
+    let available = capacity(image.header(), config)?;
+    if payload.len() > available {
+        return Err(PngError::CapacityExceeded { capacity: available, needed: payload.len() });
+    }
+
+    let bits = config.bits_per_channel as usize;
+    let framed = frame_payload(payload);
+    let positions = positions(image.header(), config)?;
+    let data = image.data_mut();
+    let mut payload_bits = framed.iter().flat_map(|&b| (0..8).rev().map(move |i| (b >> i) & 1));
+
+    'outer: for pos in positions {
+        for k in (0..bits).rev() {
+            match payload_bits.next() {
+                None => break 'outer,
+                Some(bit) => data[pos] = (data[pos] & !(1 << k)) | (bit << k),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extract a payload from decoded image data.
+pub fn extract_image(image: &ImageData, config: &LsbConfig) -> Result<Vec<u8>> {
+    let bits = config.bits_per_channel as usize;
+    let data = image.data();
+    let mut stream = positions(image.header(), config)?.into_iter()
+        .flat_map(|pos| (0..bits).rev().map(move |k| (data[pos] >> k) & 1));
+    let mut next_byte = || -> Option<u8> {
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            byte = (byte << 1) | stream.next()?;
+        }
+        Some(byte)
+    };
+
+    let mut len_bytes = [0u8; 4];
+    for b in len_bytes.iter_mut() {
+        *b = next_byte().ok_or(PngError::NoPayload)?;
+    }
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > capacity(image.header(), config)? {
+        return Err(PngError::NoPayload);
+    }
+
+    (0..len).map(|_| next_byte().ok_or(PngError::NoPayload)).collect()
+}
+
+/// Calculate how many payload bytes a PNG can hold.
+pub fn image_capacity(png: &Png, config: &LsbConfig) -> Result<usize> {
+    capacity(&png.image_header()?, config)
+}
+
+/// Embed a payload into the pixels of a PNG and re-encode the image data.
+/// Returns the chunks dropped by the copy policy of the PNG.
+pub fn embed(png: &mut Png, payload: &[u8], config: &LsbConfig, options: &EncodeOptions) -> Result<Vec<Chunk>> {
+    let mut image = png.decode_image()?;
+    embed_image(&mut image, payload, config)?;
+    png.set_image(&image, options)
+}
+
+/// Extract a payload from the pixels of a PNG.
+pub fn extract(png: &Png, config: &LsbConfig) -> Result<Vec<u8>> {
+    extract_image(&png.decode_image()?, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_image(bit_depth: u8, color_type: u8) -> ImageData {
+        let header = ImageHeader {
+            width: 20,
+            height: 10,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let len = header.row_bytes(header.width).unwrap() * header.height as usize;
+        ImageData::new(header, (0..len).map(|i| (i * 7) as u8).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload = b"This is where your secret message will be!";
+        for (bit_depth, color_type, bits, passphrase) in
+            [(8, 6, 1, None), (16, 2, 3, Some("hunter2")), (8, 4, 8, Some("x"))] {
+            let config = LsbConfig {
+                bits_per_channel: bits,
+                channels: Channels::from_str("rgba").unwrap(),
+                passphrase: passphrase.map(String::from),
+            };
+            let original = testing_image(bit_depth, color_type);
+            let mut image = original.clone();
+            embed_image(&mut image, payload, &config).unwrap();
+            assert_eq!(extract_image(&image, &config).unwrap(), payload.to_vec());
+
+            let max_delta = image.data().iter().zip(original.data())
+                .map(|(&a, &b)| (a as i16 - b as i16).unsigned_abs())
+                .max().unwrap();
+            assert!(max_delta < 1 << bits);
+        }
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let config = LsbConfig { passphrase: Some("right".to_string()), ..LsbConfig::default() };
+        let mut image = testing_image(8, 2);
+        embed_image(&mut image, b"secret", &config).unwrap();
+
+        let wrong = LsbConfig { passphrase: Some("wrong".to_string()), ..LsbConfig::default() };
+        assert_ne!(extract_image(&image, &wrong).ok(), Some(b"secret".to_vec()));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = testing_image(8, 6);
+        let config = LsbConfig::default();
+        assert_eq!(capacity(image.header(), &config).unwrap(), 20 * 10 * 3 / 8 - 4);
+
+        let mut image = image;
+        let too_big = vec![0u8; 72];
+        assert!(matches!(embed_image(&mut image, &too_big, &config),
+            Err(PngError::CapacityExceeded { capacity: 71, needed: 72 })));
+    }
+}
@@ -0,0 +1,329 @@
+//! Hides payloads in the order of palette entries of indexed images.
+//!
+//! A palette of n distinct entries can be arranged in n! orders. The payload, prefixed with its
+//! 16-bit length, is read as a number and written as a permutation of the palette relative to its
+//! sorted order. Image indices, tRNS, hIST and bKGD are remapped to match, so the rendered image is
+//! pixel-identical to the original. Animated images are refused, as their frames are not remapped.
+
+use std::str::FromStr;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::image::{get_pixel, set_pixel, EncodeOptions};
+use crate::png::{Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// A palette entry: red, green, blue and alpha.
+type Entry = [u8; 4];
+
+/// An unsigned integer of arbitrary size, stored as little-endian 32-bit limbs.
+struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn from_be_bytes(bytes: &[u8]) -> BigUint {
+        let mut n = BigUint { limbs: Vec::new() };
+        for &b in bytes {
+            n.mul_add(256, b as u32);
+        }
+        n
+    }
+
+    fn mul_add(&mut self, m: u32, a: u32) {
+        let mut carry = a as u64;
+        for limb in self.limbs.iter_mut() {
+            let v = *limb as u64 * m as u64 + carry;
+            *limb = v as u32;
+            carry = v >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    fn div_rem(&mut self, d: u32) -> u32 {
+        let mut rem = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let v = (rem << 32) | *limb as u64;
+            *limb = (v / d as u64) as u32;
+            rem = v % d as u64;
+        }
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        rem as u32
+    }
+
+    fn bit_len(&self) -> usize {
+        match self.limbs.last() {
+            None => 0,
+            Some(top) => 32 * (self.limbs.len() - 1) + (32 - top.leading_zeros() as usize),
+        }
+    }
+
+    /// Write as exactly `len` big-endian bytes, dropping any higher bytes.
+    fn into_be_bytes(mut self, len: usize) -> Vec<u8> {
+        let mut out = vec![0u8; len];
+        for b in out.iter_mut().rev() {
+            *b = self.div_rem(256) as u8;
+        }
+        out
+    }
+}
+
+/// Read the palette with alpha values from tRNS.
+fn read_palette(png: &Png) -> Result<Vec<Entry>> {
+    let header = png.image_header()?;
+    if header.color_type != 3 {
+        return Err(PngError::Unsupported("palette embedding requires an indexed image".to_string()));
+    }
+    let plte_type = ChunkType::from_str("PLTE").unwrap();
+    let plte = png.chunk_by_type(&plte_type).ok_or(PngError::ChunkNotFound(plte_type))?;
+    if plte.data().len() % 3 != 0 || plte.data().len() > 3 * 256 {
+        return Err(PngError::MalformedChunk("PLTE".to_string()));
+    }
+    let trns = png.chunk_by_type(&ChunkType::from_str("tRNS").unwrap()).map(|c| c.data()).unwrap_or(&[]);
+
+    let entries: Vec<Entry> = plte.data().chunks_exact(3).enumerate()
+        .map(|(i, rgb)| [rgb[0], rgb[1], rgb[2], *trns.get(i).unwrap_or(&255)])
+        .collect();
+
+    let mut sorted = entries.clone();
+    sorted.sort();
+    sorted.dedup();
+    if sorted.len() != entries.len() {
+        return Err(PngError::Unsupported("palette has duplicate entries".to_string()));
+    }
+    Ok(entries)
+}
+
+/// Get the number of framed bytes a palette of `n` entries can hold: the largest k with 256^k <= n!.
+fn framed_capacity(n: usize) -> usize {
+    let mut factorial = BigUint { limbs: vec![1] };
+    for i in 2..=n {
+        factorial.mul_add(i as u32, 0);
+    }
+    factorial.bit_len().saturating_sub(1) / 8
+}
+
+/// Calculate how many payload bytes the palette of a PNG can hold.
+pub fn capacity(png: &Png) -> Result<usize> {
+    Ok(framed_capacity(read_palette(png)?.len()).saturating_sub(2))
+}
+
+/// Embed a payload by reordering the palette and remapping the image data.
+/// Returns the chunks dropped by the copy policy of the PNG.
+pub fn embed(png: &mut Png, payload: &[u8], options: &EncodeOptions) -> Result<Vec<Chunk>> {
+    // Only the default image is remapped; the frames of an animation would keep the old indices
+    if png.is_animated() {
+        return Err(PngError::Unsupported("palette embedding in animated images".to_string()));
+    }
+    let entries = read_palette(png)?;
+    let n = entries.len();
+    let hist_type = ChunkType::from_str("hIST").unwrap();
+    if png.chunk_by_type(&hist_type).is_some_and(|h| h.data().len() != 2 * n) {
+        return Err(PngError::MalformedChunk(hist_type.to_string()));
+    }
+    let framed_len = framed_capacity(n);
+    if payload.len() + 2 > framed_len || payload.len() > u16::MAX as usize {
+        return Err(PngError::CapacityExceeded { capacity: framed_len.saturating_sub(2), needed: payload.len() });
+    }
+
+    let mut framed = (payload.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(payload);
+    framed.resize(framed_len, 0);
+    let mut number = BigUint::from_be_bytes(&framed);
+
+    let mut available = entries.clone();
+    available.sort();
+    let mut new_entries = Vec::<Entry>::with_capacity(n);
+    for radix in (1..=n).rev() {
+        new_entries.push(available.remove(number.div_rem(radix as u32) as usize));
+    }
+
+    // old index -> new index
+    let remap: Vec<u8> = entries.iter()
+        .map(|e| new_entries.iter().position(|x| x == e).unwrap() as u8)
+        .collect();
+
+    let mut image = png.decode_image()?;
+    let bits = image.header().bit_depth as usize;
+    let (width, row_bytes) = (image.header().width as usize, image.row_bytes());
+    for row in image.data_mut().chunks_mut(row_bytes) {
+        for x in 0..width {
+            let old = get_pixel(row, x, bits) as usize;
+            if let Some(&new) = remap.get(old) {
+                set_pixel(row, x, bits, new as u64);
+            }
+        }
+    }
+
+    let plte: Vec<u8> = new_entries.iter().flat_map(|e| e[0..3].to_vec()).collect();
+    let mut dropped = png.replace_chunks(&ChunkType::from_str("PLTE").unwrap(),
+                                         vec![Chunk::new(&ChunkType::from_str("PLTE").unwrap(), plte)]);
+
+    let trns_type = ChunkType::from_str("tRNS").unwrap();
+    if png.chunk_by_type(&trns_type).is_some() {
+        let trns: Vec<u8> = new_entries.iter().map(|e| e[3]).collect();
+        png.replace_chunks(&trns_type, vec![Chunk::new(&trns_type, trns)]);
+    }
+    if let Some(hist) = png.chunk_by_type(&hist_type) {
+        let mut new_hist = vec![0u8; hist.data().len()];
+        for (old, freq) in hist.data().chunks_exact(2).enumerate() {
+            let new = remap[old] as usize * 2;
+            new_hist[new..new + 2].copy_from_slice(freq);
+        }
+        png.replace_chunks(&hist_type, vec![Chunk::new(&hist_type, new_hist)]);
+    }
+    let bkgd_type = ChunkType::from_str("bKGD").unwrap();
+    if let Some(bkgd) = png.chunk_by_type(&bkgd_type) {
+        if let Some(&new) = bkgd.data().first().and_then(|&old| remap.get(old as usize)) {
+            png.replace_chunks(&bkgd_type, vec![Chunk::new(&bkgd_type, vec![new])]);
+        }
+    }
+
+    dropped.append(&mut png.set_image(&image, options)?);
+    Ok(dropped)
+}
+
+/// Extract a payload from the order of the palette.
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let entries = read_palette(png)?;
+    let n = entries.len();
+    let framed_len = framed_capacity(n);
+
+    let mut available = entries.clone();
+    available.sort();
+    let digits: Vec<u32> = entries.iter()
+        .map(|e| {
+            let d = available.iter().position(|x| x == e).unwrap();
+            available.remove(d);
+            d as u32
+        })
+        .collect();
+
+    let mut number = BigUint { limbs: Vec::new() };
+    for (i, &d) in digits.iter().enumerate().rev() {
+        number.mul_add((n - i) as u32, d);
+    }
+    let framed = number.into_be_bytes(framed_len);
+    if framed_len < 2 {
+        return Err(PngError::NoPayload);
+    }
+
+    let len = u16::from_be_bytes([framed[0], framed[1]]) as usize;
+    if len == 0 || len + 2 > framed_len {
+        return Err(PngError::NoPayload);
+    }
+    Ok(framed[2..2 + len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageData;
+    use crate::png::ImageHeader;
+
+    fn testing_png(bit_depth: u8, entries: usize) -> Png {
+        let header = ImageHeader {
+            width: 10,
+            height: 6,
+            bit_depth,
+            color_type: 3,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let plte: Vec<u8> = (0..entries * 3).map(|i| (i * 53 % 256) as u8).collect();
+        let mut png = Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(&ChunkType::from_str("PLTE").unwrap(), plte),
+            Chunk::new(&ChunkType::from_str("tRNS").unwrap(), vec![0, 128]),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ]);
+
+        let mut data = vec![0u8; header.row_bytes(10).unwrap() * 6];
+        let row_bytes = data.len() / 6;
+        for y in 0..6 {
+            for x in 0..10 {
+                set_pixel(&mut data[y * row_bytes..], x, bit_depth as usize, ((x + y) % entries) as u64);
+            }
+        }
+        png.set_image(&ImageData::new(header, data).unwrap(), &EncodeOptions::default()).unwrap();
+        png
+    }
+
+    /// Render the image as RGBA entries.
+    fn rendered(png: &Png) -> Vec<Entry> {
+        let entries = read_palette(png).unwrap();
+        let image = png.decode_image().unwrap();
+        let bits = image.header().bit_depth as usize;
+        image.data().chunks(image.row_bytes())
+            .flat_map(|row| (0..10).map(move |x| get_pixel(row, x, bits) as usize))
+            .map(|i| entries[i])
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (bit_depth, entries, payload) in [(8, 200, &b"This is where your secret message will be!"[..]), (4, 16, b"abc")] {
+            let mut png = testing_png(bit_depth, entries);
+            let before = rendered(&png);
+            embed(&mut png, payload, &EncodeOptions::default()).unwrap();
+            assert_eq!(rendered(&png), before);
+            assert_eq!(extract(&png).unwrap(), payload.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_bad_histogram() {
+        for len in [31, 34] {
+            let mut png = testing_png(4, 16);
+            png.insert_before(&ChunkType::from_str("IDAT").unwrap(),
+                              Chunk::new(&ChunkType::from_str("hIST").unwrap(), vec![0; len])).unwrap();
+            assert!(matches!(embed(&mut png, b"abc", &EncodeOptions::default()), Err(PngError::MalformedChunk(_))));
+        }
+    }
+
+    #[test]
+    fn test_bad_palette() {
+        for len in [47, 3 * 257] {
+            let mut png = testing_png(8, 16);
+            let plte_type = ChunkType::from_str("PLTE").unwrap();
+            png.replace_chunks(&plte_type, vec![Chunk::new(&plte_type, (0..len).map(|i| i as u8).collect())]);
+            assert!(matches!(capacity(&png), Err(PngError::MalformedChunk(_))));
+        }
+    }
+
+    #[test]
+    fn test_animated_refused() {
+        let mut png = testing_png(4, 16);
+        png.insert_before(&ChunkType::from_str("IDAT").unwrap(),
+                          Chunk::new(&ChunkType::from_str("acTL").unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 0])).unwrap();
+        let before = png.as_bytes();
+        assert!(matches!(embed(&mut png, b"abc", &EncodeOptions::default()), Err(PngError::Unsupported(_))));
+        assert_eq!(png.as_bytes(), before);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let png = testing_png(4, 16);
+        // 16! is just above 2^44, so five framed bytes fit
+        assert_eq!(capacity(&png).unwrap(), 3);
+
+        let mut png = png;
+        assert!(matches!(embed(&mut png, b"abcd", &EncodeOptions::default()),
+            Err(PngError::CapacityExceeded { capacity: 3, needed: 4 })));
+    }
+
+    #[test]
+    fn test_sorted_palette_has_no_payload() {
+        // An empty payload encodes as zero, which leaves the palette in sorted order
+        let mut png = testing_png(8, 50);
+        embed(&mut png, b"", &EncodeOptions::default()).unwrap();
+        let entries = read_palette(&png).unwrap();
+        assert!(entries.windows(2).all(|w| w[0] < w[1]));
+        assert!(matches!(extract(&png), Err(PngError::NoPayload)));
+    }
+}
@@ -0,0 +1,248 @@
+//! Hides payloads in the IDAT zlib stream where decoders do not look.
+//!
+//! Decoders stop reading IDAT data once the zlib stream ends, and stop using decompressed data
+//! once every scanline of the image has been read. A payload can therefore follow the Adler-32
+//! trailer in the last IDAT chunk, sit in an extra IDAT chunk after the stream, or be appended to
+//! the deflate stream in stored blocks whose output is never used. The pixels and the chunk
+//! listing of the image are unchanged apart from chunk lengths.
+
+use std::str::FromStr;
+
+use flate2::{Compress, Compression, FlushCompress};
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::image::{deflate, filtered_len, inflate, zlib_stream_len};
+use crate::payload::MAX_UNPACKED_LEN;
+use crate::png::{Png, PngError};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Largest amount of data a single stored deflate block can hold.
+const MAX_STORED_BLOCK: usize = 65535;
+
+/// Where in the image stream the payload is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamMode {
+    /// After the Adler-32 trailer, in the last IDAT chunk of the stream.
+    #[default]
+    Trailer,
+    /// In a separate IDAT chunk following the end of the stream.
+    ExtraIdat,
+    /// In stored deflate blocks after the scanlines, inside the compressed stream.
+    StoredBlock,
+}
+
+impl FromStr for StreamMode {
+    type Err = PngError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "trailer" => Ok(StreamMode::Trailer),
+            "extra-idat" => Ok(StreamMode::ExtraIdat),
+            "stored-block" => Ok(StreamMode::StoredBlock),
+            _ => Err(PngError::Unsupported(format!("unknown stream mode {s}"))),
+        }
+    }
+}
+
+/// Embed a payload in the image stream, replacing any payload already there.
+/// Returns the chunks dropped by the copy policy of the PNG.
+pub fn embed(png: &mut Png, payload: &[u8], mode: StreamMode, level: u32) -> Result<Vec<Chunk>> {
+    let idat_type = ChunkType::from_str("IDAT").unwrap();
+    let mut dropped = remove(png, level).map(|(_, dropped)| dropped).or_else(|e| match e {
+        PngError::NoPayload => Ok(Vec::new()),
+        e => Err(e),
+    })?;
+
+    let mut idats: Vec<Chunk> = png.chunks().iter()
+        .filter(|c| c.chunk_type() == &idat_type)
+        .map(|c| Chunk::new(&idat_type, c.data().to_vec()))
+        .collect();
+    match mode {
+        StreamMode::Trailer => {
+            let last = idats.pop().ok_or_else(|| PngError::ChunkNotFound(idat_type.clone()))?;
+            let mut data = last.data().to_vec();
+            data.extend_from_slice(payload);
+            idats.push(Chunk::new(&idat_type, data));
+        }
+        StreamMode::ExtraIdat => {
+            idats.push(Chunk::new(&idat_type, payload.to_vec()));
+        }
+        StreamMode::StoredBlock => {
+            let needed = filtered_len(&png.image_header()?)?;
+            let mut filtered = inflate(&png.image_stream(), needed)?;
+            filtered.truncate(needed);
+            idats = vec![Chunk::new(&idat_type, stored_block_stream(&filtered, payload, level))];
+        }
+    }
+
+    dropped.append(&mut png.replace_chunks(&idat_type, idats));
+    Ok(dropped)
+}
+
+/// Extract a payload from the image stream, whichever mode was used to hide it.
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let stream = png.image_stream();
+    let len = zlib_stream_len(&stream)?;
+    if len < stream.len() {
+        return Ok(stream[len..].to_vec());
+    }
+
+    let needed = filtered_len(&png.image_header()?)?;
+    let filtered = inflate(&stream, needed.saturating_add(MAX_UNPACKED_LEN))?;
+    if filtered.len() > needed.saturating_add(MAX_UNPACKED_LEN) {
+        return Err(PngError::BadPayload(format!("payload is longer than {MAX_UNPACKED_LEN} bytes")));
+    }
+    if filtered.len() > needed {
+        return Ok(filtered[needed..].to_vec());
+    }
+    Err(PngError::NoPayload)
+}
+
+/// Remove a payload from the image stream, returning it along with the chunks dropped by the
+/// copy policy of the PNG. Stored-block payloads are removed by recompressing the scanlines.
+pub fn remove(png: &mut Png, level: u32) -> Result<(Vec<u8>, Vec<Chunk>)> {
+    let payload = extract(png)?;
+    let idat_type = ChunkType::from_str("IDAT").unwrap();
+    let stream = png.image_stream();
+    let len = zlib_stream_len(&stream)?;
+
+    let idats = if len < stream.len() {
+        // Keep the original chunk boundaries, cutting at the end of the zlib stream
+        let mut remaining = len;
+        let mut idats = Vec::<Chunk>::new();
+        for c in png.chunks().iter().filter(|c| c.chunk_type() == &idat_type) {
+            if remaining == 0 {
+                break;
+            }
+            let take = remaining.min(c.data().len());
+            idats.push(Chunk::new(&idat_type, c.data()[..take].to_vec()));
+            remaining -= take;
+        }
+        idats
+    }
+    else {
+        let needed = filtered_len(&png.image_header()?)?;
+        let mut filtered = inflate(&stream, needed)?;
+        filtered.truncate(needed);
+        vec![Chunk::new(&idat_type, deflate(&filtered, level))]
+    };
+
+    let dropped = png.replace_chunks(&idat_type, idats);
+    Ok((payload, dropped))
+}
+
+/// Compress the scanlines and follow them with the payload in stored blocks, so the payload
+/// decompresses after the image data.
+fn stored_block_stream(filtered: &[u8], payload: &[u8], level: u32) -> Vec<u8> {
+    let mut out = Vec::<u8>::with_capacity(filtered.len() + payload.len() + 1024);
+    out.extend_from_slice(&[0x78, 0x9c]);
+
+    // A sync flush ends the compressed scanlines on a byte boundary without a final block
+    let mut compress = Compress::new(Compression::new(level.min(9)), false);
+    loop {
+        let consumed = compress.total_in() as usize;
+        compress.compress_vec(&filtered[consumed..], &mut out, FlushCompress::Sync)
+            .expect("compressing to a vector cannot fail");
+        if compress.total_in() as usize == filtered.len() && out.len() < out.capacity() {
+            break;
+        }
+        out.reserve(64 * 1024);
+    }
+
+    let mut blocks: Vec<&[u8]> = payload.chunks(MAX_STORED_BLOCK).collect();
+    if blocks.is_empty() {
+        blocks.push(&[]);
+    }
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.into_iter().enumerate() {
+        out.push(if i == last { 1 } else { 0 });
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(&[filtered, payload]).to_be_bytes());
+    out
+}
+
+/// Compute the Adler-32 checksum of the concatenated parts.
+fn adler32(parts: &[&[u8]]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in parts.iter().flat_map(|p| p.iter()) {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{EncodeOptions, ImageData};
+    use crate::png::ImageHeader;
+
+    fn testing_png() -> Png {
+        let header = ImageHeader {
+            width: 12,
+            height: 9,
+            bit_depth: 8,
+            color_type: 2,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let mut png = Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(&ChunkType::END_CHUNK, Vec::new()),
+        ]);
+        let data: Vec<u8> = (0..12 * 9 * 3).map(|i| (i * 7 % 251) as u8).collect();
+        png.set_image(&ImageData::new(header, data).unwrap(), &EncodeOptions::default()).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for mode in [StreamMode::Trailer, StreamMode::ExtraIdat, StreamMode::StoredBlock] {
+            let mut png = testing_png();
+            let image = png.decode_image().unwrap();
+            embed(&mut png, b"hidden in the stream", mode, 6).unwrap();
+
+            assert_eq!(png.decode_image().unwrap(), image);
+            assert_eq!(extract(&png).unwrap(), b"hidden in the stream".to_vec());
+        }
+    }
+
+    #[test]
+    fn test_extra_idat_adds_chunk() {
+        let mut png = testing_png();
+        embed(&mut png, b"abc", StreamMode::ExtraIdat, 6).unwrap();
+        assert_eq!(png.chunks().iter().filter(|c| c.chunk_type() == "IDAT").count(), 2);
+    }
+
+    #[test]
+    fn test_large_stored_payload() {
+        let mut png = testing_png();
+        let payload: Vec<u8> = (0..MAX_STORED_BLOCK * 2 + 10).map(|i| i as u8).collect();
+        embed(&mut png, &payload, StreamMode::StoredBlock, 6).unwrap();
+        assert_eq!(extract(&png).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_replaces_and_remove() {
+        let mut png = testing_png();
+        let image = png.decode_image().unwrap();
+        embed(&mut png, b"first", StreamMode::StoredBlock, 6).unwrap();
+        embed(&mut png, b"second", StreamMode::Trailer, 6).unwrap();
+        assert_eq!(extract(&png).unwrap(), b"second".to_vec());
+
+        assert_eq!(remove(&mut png, 6).unwrap().0, b"second".to_vec());
+        assert!(matches!(extract(&png), Err(PngError::NoPayload)));
+        assert_eq!(png.decode_image().unwrap(), image);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(&[b"Wiki", b"pedia"]), 0x11E60398);
+    }
+}
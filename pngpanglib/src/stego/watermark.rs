@@ -0,0 +1,321 @@
+//! Embeds a robust watermark in image samples with a spread-spectrum pattern.
+//!
+//! Every color sample carries one bit of a 32-bit identifier. A secret seeds which bit each sample
+//! carries and a ±1 pattern; embedding adds the pattern, signed by the bit, at a small strength.
+//! Detection needs only the secret: it removes most of the image content by subtracting the mean
+//! of each sample's neighbors, correlates the residual with the pattern and reports a z-score per
+//! bit. Unlike LSB embedding, the watermark survives re-encoding and small changes to the pixels.
+
+use crate::chunk::Chunk;
+use crate::image::{EncodeOptions, ImageData};
+use crate::png::{ImageHeader, Png, PngError};
+use crate::stego::{visit_order, Prng};
+
+type Result<T> = std::result::Result<T, PngError>;
+
+/// Number of bits in a watermark identifier.
+pub const ID_BITS: usize = 32;
+
+/// Mean absolute z-score above which a watermark is considered present.
+pub const DETECTION_THRESHOLD: f64 = 2.0;
+
+/// Largest residual used in detection, in units of an 8-bit sample.
+const RESIDUAL_LIMIT: f64 = 12.0;
+
+/// Fewest samples that may carry each identifier bit.
+const MIN_SAMPLES_PER_BIT: usize = 16;
+
+/// Settings for watermarking. Detection must use the same secret as embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatermarkConfig {
+    /// Secret seeding the pattern.
+    pub secret: String,
+    /// Amplitude of the pattern, in units of an 8-bit sample.
+    pub strength: f64,
+}
+
+impl WatermarkConfig {
+    /// Create settings with the default strength.
+    pub fn new(secret: &str) -> WatermarkConfig {
+        WatermarkConfig { secret: secret.to_string(), strength: 2.0 }
+    }
+}
+
+/// The result of looking for a watermark.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    /// The identifier read from the pattern. Meaningless unless [Detection::is_present].
+    pub id: u32,
+    /// Mean absolute z-score over all bits. Near 0.8 for images without this watermark.
+    pub confidence: f64,
+    /// Z-score of each bit, most significant first. The sign gives the bit value.
+    pub bit_scores: Vec<f64>,
+}
+
+impl Detection {
+    /// Check whether the confidence is high enough to consider the watermark present.
+    pub fn is_present(&self) -> bool {
+        self.confidence >= DETECTION_THRESHOLD
+    }
+}
+
+/// Sample layout of an image: byte offset of every color sample and whether it is visible.
+struct Layout {
+    offsets: Vec<usize>,
+    visible: Vec<bool>,
+    sample_bytes: usize,
+    colors: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Layout {
+    fn new(header: &ImageHeader) -> Result<Layout> {
+        if !matches!(header.bit_depth, 8 | 16) || header.color_type == 3 {
+            return Err(PngError::Unsupported("watermarks in indexed or sub-byte images".to_string()));
+        }
+        let colors = match header.color_type {
+            0 | 4 => 1,
+            2 | 6 => 3,
+            _ => return Err(PngError::MalformedChunk(ImageHeader::CHUNK_TYPE.to_string())),
+        };
+        let sample_bytes = header.bit_depth as usize / 8;
+        let pixel_bytes = header.channels()? * sample_bytes;
+        let (width, height) = (header.width as usize, header.height as usize);
+        if width * height * colors < ID_BITS * MIN_SAMPLES_PER_BIT {
+            return Err(PngError::Unsupported("image too small for a watermark".to_string()));
+        }
+
+        let offsets = (0..width * height)
+            .flat_map(|p| (0..colors).map(move |c| p * pixel_bytes + c * sample_bytes))
+            .collect();
+        Ok(Layout { offsets, visible: Vec::new(), sample_bytes, colors, width, height })
+    }
+
+    /// Mark the samples of fully transparent pixels, which are often zeroed by optimizers.
+    fn find_visible(&mut self, header: &ImageHeader, data: &[u8]) {
+        let has_alpha = matches!(header.color_type, 4 | 6);
+        let alpha_offset = self.colors * self.sample_bytes;
+        self.visible = self.offsets.iter().enumerate()
+            .map(|(i, &o)| {
+                let alpha = o - (i % self.colors) * self.sample_bytes + alpha_offset;
+                !has_alpha || data[alpha..alpha + self.sample_bytes].iter().any(|&b| b != 0)
+            })
+            .collect();
+    }
+
+    fn scale(&self) -> f64 {
+        if self.sample_bytes == 2 { 257.0 } else { 1.0 }
+    }
+
+    fn get(&self, data: &[u8], i: usize) -> f64 {
+        let o = self.offsets[i];
+        match self.sample_bytes {
+            2 => u16::from_be_bytes([data[o], data[o + 1]]) as f64,
+            _ => data[o] as f64,
+        }
+    }
+
+    fn set(&self, data: &mut [u8], i: usize, value: f64) {
+        let o = self.offsets[i];
+        match self.sample_bytes {
+            2 => data[o..o + 2].copy_from_slice(&(value.round().clamp(0.0, 65535.0) as u16).to_be_bytes()),
+            _ => data[o] = value.round().clamp(0.0, 255.0) as u8,
+        }
+    }
+
+    /// Get the sample minus the mean of its visible horizontal and vertical neighbors in the same
+    /// channel, clipped so that edges do not drown out the pattern.
+    fn residual(&self, data: &[u8], i: usize) -> Option<f64> {
+        let (p, c) = (i / self.colors, i % self.colors);
+        let (x, y) = (p % self.width, p / self.width);
+        let mut neighbors = Vec::<usize>::with_capacity(4);
+        if x > 0 { neighbors.push(p - 1); }
+        if x + 1 < self.width { neighbors.push(p + 1); }
+        if y > 0 { neighbors.push(p - self.width); }
+        if y + 1 < self.height { neighbors.push(p + self.width); }
+        let samples: Vec<f64> = neighbors.iter()
+            .map(|&q| q * self.colors + c)
+            .filter(|&j| self.visible[j])
+            .map(|j| self.get(data, j))
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+
+        let limit = RESIDUAL_LIMIT * self.scale();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        Some((self.get(data, i) - mean).clamp(-limit, limit))
+    }
+}
+
+/// Assign each sample an identifier bit and a ±1 pattern value.
+fn pattern(n: usize, secret: &str) -> (Vec<usize>, Vec<f64>) {
+    let mut bit_of = vec![0usize; n];
+    for (j, i) in visit_order(n, Some(secret)).into_iter().enumerate() {
+        bit_of[i] = j % ID_BITS;
+    }
+    let mut prng = Prng::from_passphrase(&format!("{secret}\0watermark"));
+    let signs = (0..n).map(|_| if prng.next_u64() & 1 == 1 { 1.0 } else { -1.0 }).collect();
+    (bit_of, signs)
+}
+
+/// Embed a watermark identifier into decoded image data.
+pub fn embed_image(image: &mut ImageData, id: u32, config: &WatermarkConfig) -> Result<()> {
+    let mut layout = Layout::new(image.header())?;
+    layout.find_visible(image.header(), image.data());
+    let n = layout.offsets.len();
+    let (bit_of, signs) = pattern(n, &config.secret);
+    let amplitude = config.strength * layout.scale();
+    let data = image.data_mut();
+
+    for i in (0..n).filter(|&i| layout.visible[i]) {
+        let bit = (id >> (ID_BITS - 1 - bit_of[i])) & 1;
+        let direction = if bit == 1 { 1.0 } else { -1.0 };
+        let value = layout.get(data, i) + amplitude * signs[i] * direction;
+        layout.set(data, i, value);
+    }
+    Ok(())
+}
+
+/// Look for a watermark in decoded image data.
+pub fn detect_image(image: &ImageData, config: &WatermarkConfig) -> Result<Detection> {
+    let mut layout = Layout::new(image.header())?;
+    layout.find_visible(image.header(), image.data());
+    let n = layout.offsets.len();
+    let (bit_of, signs) = pattern(n, &config.secret);
+    let data = image.data();
+
+    // Per bit: count, sum and sum of squares of the residual correlated with the pattern
+    let mut stats = [(0f64, 0f64, 0f64); ID_BITS];
+    for i in (0..n).filter(|&i| layout.visible[i]) {
+        let Some(r) = layout.residual(data, i) else {
+            continue;
+        };
+        let q = r * signs[i];
+        let s = &mut stats[bit_of[i]];
+        s.0 += 1.0;
+        s.1 += q;
+        s.2 += q * q;
+    }
+
+    let bit_scores: Vec<f64> = stats.iter()
+        .map(|&(count, sum, sum_sq)| {
+            if count < 2.0 {
+                return 0.0;
+            }
+            let mean = sum / count;
+            let variance = (sum_sq / count - mean * mean).max(f64::EPSILON);
+            mean / (variance / count).sqrt()
+        })
+        .collect();
+    let id = bit_scores.iter().fold(0u32, |id, &z| (id << 1) | (z > 0.0) as u32);
+    let confidence = bit_scores.iter().map(|z| z.abs()).sum::<f64>() / ID_BITS as f64;
+
+    Ok(Detection { id, confidence, bit_scores })
+}
+
+/// Embed a watermark identifier into the pixels of a PNG and re-encode the image data.
+/// Returns the chunks dropped by the copy policy of the PNG.
+pub fn embed(png: &mut Png, id: u32, config: &WatermarkConfig, options: &EncodeOptions) -> Result<Vec<Chunk>> {
+    let mut image = png.decode_image()?;
+    embed_image(&mut image, id, config)?;
+    png.set_image(&image, options)
+}
+
+/// Look for a watermark in the pixels of a PNG.
+pub fn detect(png: &Png, config: &WatermarkConfig) -> Result<Detection> {
+    detect_image(&png.decode_image()?, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::FilterStrategy;
+    use crate::stego::lsb::{self, LsbConfig};
+
+    const ID: u32 = 0xC0FFEE42;
+
+    fn testing_image(bit_depth: u8, color_type: u8) -> ImageData {
+        let header = ImageHeader {
+            width: 64,
+            height: 48,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let channels = header.channels().unwrap();
+        let mut prng = Prng::from_passphrase("texture");
+        let mut data = Vec::<u8>::new();
+        for y in 0..48 {
+            for x in 0..64 {
+                for c in 0..channels {
+                    let v = (40 + x * 2 + y + c * 30) as u16 + prng.below(7) as u16;
+                    match bit_depth {
+                        16 => data.extend_from_slice(&(v * 257).to_be_bytes()),
+                        _ => data.push(v as u8),
+                    }
+                }
+            }
+        }
+        ImageData::new(header, data).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_detect() {
+        for (bit_depth, color_type) in [(8, 2), (8, 6), (16, 0)] {
+            let mut image = testing_image(bit_depth, color_type);
+            let config = WatermarkConfig::new("secret");
+            embed_image(&mut image, ID, &config).unwrap();
+
+            let detection = detect_image(&image, &config).unwrap();
+            assert!(detection.is_present(), "{detection:?}");
+            assert_eq!(detection.id, ID);
+        }
+    }
+
+    #[test]
+    fn test_absent_without_watermark_or_secret() {
+        let mut image = testing_image(8, 2);
+        assert!(!detect_image(&image, &WatermarkConfig::new("secret")).unwrap().is_present());
+
+        embed_image(&mut image, ID, &WatermarkConfig::new("secret")).unwrap();
+        assert!(!detect_image(&image, &WatermarkConfig::new("other")).unwrap().is_present());
+    }
+
+    #[test]
+    fn test_survives_reencoding_and_lsb() {
+        let image = testing_image(8, 2);
+        let mut png = Png::from_chunks(vec![
+            image.header().to_chunk(),
+            Chunk::new(&crate::chunk::ChunkType::END_CHUNK, Vec::new()),
+        ]);
+        png.set_image(&image, &EncodeOptions::default()).unwrap();
+
+        let config = WatermarkConfig::new("secret");
+        embed(&mut png, ID, &config, &EncodeOptions { filter: FilterStrategy::Paeth, compression: 1 }).unwrap();
+        let capacity = lsb::image_capacity(&png, &LsbConfig::default()).unwrap();
+        lsb::embed(&mut png, &vec![0xA5; capacity], &LsbConfig::default(), &EncodeOptions::default()).unwrap();
+
+        let detection = detect(&png, &config).unwrap();
+        assert!(detection.is_present(), "{detection:?}");
+        assert_eq!(detection.id, ID);
+    }
+
+    #[test]
+    fn test_too_small() {
+        let header = ImageHeader {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: 2,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let image = ImageData::new(header, vec![0; 48]).unwrap();
+        assert!(matches!(detect_image(&image, &WatermarkConfig::new("secret")), Err(PngError::Unsupported(_))));
+    }
+}